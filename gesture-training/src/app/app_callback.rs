@@ -2,22 +2,30 @@ use std::cell::RefCell;
 
 use std::rc::Rc;
 use std::str::FromStr;
-use std::time::Duration;
 
 pub use slint::ComponentHandle;
-use slint::SharedString;
+use slint::{ModelRc, SharedString, VecModel};
 use uuid::Uuid;
 
 use crate::app::app_ui::WeakAppUi;
-use crate::app::backend::{AppBackendModifications, ImageSourceModification};
-use crate::app::image_source::{ImageSource, ImageSourceTrait};
+use crate::app::backend::{AppBackendModifications, ImageSourceModification, SessionModification};
+use crate::app::image_source::archive::ArchiveFormat;
+use crate::app::image_source::folder::{FolderFilter, ImageSourceFolder};
+use crate::app::image_source::list::ImageSourceList;
+use crate::app::image_source::{
+    DisplayTarget, ImageSource, ImageSourceBackend, ImageSourceStatus, ImageSourceTrait,
+    ImageSourceWatcher, WatchedPath,
+};
 use crate::app::log::Log;
-use crate::app::session::AppSessionConfiguration;
+use crate::app::session::{parse_seed, AppSessionConfiguration, SessionPlan};
 use crate::app::{App, AppUi};
 use crate::sg;
 
 type RcBackend = Rc<RefCell<super::backend::AppBackend>>;
 
+/// Thumbnails displayed by the source editors
+const EDITOR_THUMBNAIL_COUNT: usize = 12;
+
 /// Use this structure inside callbacks to perform logic
 #[derive(Clone)]
 struct AppCallback {
@@ -45,6 +53,29 @@ impl AppCallback {
         }
     }
 
+    /// Watch the folders of the image sources to check them again when their files change
+    fn update_watched_image_sources(&self) {
+        let backend = self.backend.borrow();
+        let mut app = self.app.borrow_mut();
+        if let Some(watcher) = app.image_source_watcher.as_mut() {
+            watcher.update(
+                backend
+                    .image_sources()
+                    .image_sources()
+                    .into_iter()
+                    .filter_map(|image_source| match image_source {
+                        ImageSource::Folder(folder) => {
+                            Some((folder.id(), WatchedPath::Folder(folder.path.clone())))
+                        }
+                        ImageSource::List(list) => {
+                            Some((list.id(), WatchedPath::File(list.path.clone())))
+                        }
+                        ImageSource::Archive(_) | ImageSource::Web(_) => None,
+                    }),
+            );
+        }
+    }
+
     fn trigger_image_source_check_from_modifications(&self, modifications: &AppBackendModifications) {
         let changed_ids = modifications
             .image_sources()
@@ -59,12 +90,23 @@ impl AppCallback {
             callback: &AppCallback,
             uuid: Uuid,
         ) -> anyhow::Result<()> {
-            let backend = callback.backend.borrow();
-            let image_source = backend
-                .image_sources()
-                .get_image_source(uuid)
-                .ok_or(anyhow::anyhow!(""))?
-                .clone();
+            let image_source = {
+                let mut backend = callback.backend.borrow_mut();
+                let image_source = backend
+                    .image_sources()
+                    .get_image_source(uuid)
+                    .ok_or(anyhow::anyhow!(""))?
+                    .clone();
+                // Sessions wait for the check, the saved one may list removed images
+                if !backend.image_sources_mut().start_check(uuid) {
+                    // Checked again once the running check ends
+                    return Ok(());
+                }
+                image_source
+            };
+            if let Some(ui) = callback.ui.upgrade() {
+                ui.update_prepared_session_data(&callback.backend.borrow());
+            }
 
             let callback_clone = callback.clone();
             slint::spawn_local(async move {
@@ -72,28 +114,54 @@ impl AppCallback {
                     image_source: ImageSource,
                     callback: &AppCallback,
                 ) -> anyhow::Result<()> {
-                    let modifications = {
-                        let check = image_source.check_source().await;
+                    let (id, is_modified) = {
+                        let check = image_source.check_source().await.checked_now();
                         let mut backend = callback.backend.borrow_mut();
                         let image_source = backend
                             .image_sources_mut()
                             .get_image_source_mut(image_source.id())
                             .ok_or(anyhow::anyhow!(""))?;
+
+                        // The last check is persisted and already displayed,
+                        // only its timestamp is updated when the result is the same
+                        let is_modified = !image_source.check().has_same_result(&check);
+                        let id = image_source.id();
                         image_source.set_check(check);
-                        ImageSourceModification::Modified(image_source.id()).into()
+                        backend.save_to_persistence()?;
+                        (id, is_modified)
                     };
 
                     {
-                        let mut ui = callback.ui.upgrade().ok_or(anyhow::anyhow!(""))?;
+                        let ui = callback.ui.upgrade().ok_or(anyhow::anyhow!(""))?;
                         let backend = callback.backend.borrow();
-                        ui.update_with_backend_modifications(&backend, &modifications);
+                        if is_modified {
+                            let modifications = ImageSourceModification::Modified(id).into();
+                            ui.update_with_backend_modifications(&backend, &modifications);
+                        } else {
+                            ui.update_image_source_selector_entry(&backend, id);
+                        }
                     }
 
                     Ok(())
                 }
 
+                let id = image_source.id();
                 let error = execute(image_source, &callback_clone).await;
                 callback_clone.handle_error(error);
+                let check_again = callback_clone
+                    .backend
+                    .borrow_mut()
+                    .image_sources_mut()
+                    .end_check(id);
+                if check_again {
+                    // The source changed during the check, like a watched folder
+                    callback_clone.trigger_image_source_check([id]);
+                    return;
+                }
+                if let Some(ui) = callback_clone.ui.upgrade() {
+                    ui.update_prepared_session_data(&callback_clone.backend.borrow());
+                }
+                callback_clone.create_thumbnails(id);
             })?;
             Ok(())
         }
@@ -104,22 +172,132 @@ impl AppCallback {
         }
     }
 
-    fn add_or_save_folder_source(&self, data: sg::EditSourceFolderData) {
-        fn execute(this: &AppCallback, data: sg::EditSourceFolderData) -> anyhow::Result<()> {
+    /// Create the thumbnails shown by the editor of a source, in the background
+    ///
+    /// The thumbnails of the session review are created when the session is completed.
+    /// The job of the source still running is cancelled
+    fn create_thumbnails(&self, uuid: Uuid) {
+        let image_source = self
+            .backend
+            .borrow()
+            .image_sources()
+            .get_image_source(uuid)
+            .filter(|image_source| image_source.check().status() == &ImageSourceStatus::Valid)
+            .cloned();
+
+        if let Some(image_source) = image_source {
+            let cancelled = self.app.borrow_mut().thumbnail_jobs.restart(uuid);
+            let error = slint::spawn_local(async move {
+                image_source
+                    .create_thumbnails(EDITOR_THUMBNAIL_COUNT, cancelled)
+                    .await;
+            })
+            .map_err(anyhow::Error::from);
+            self.handle_error(error);
+        }
+    }
+
+    /// Load thumbnails in the background, failing images are left out
+    async fn load_thumbnails(images: Vec<(ImageSource, usize)>) -> ModelRc<slint::Image> {
+        let mut thumbnails = Vec::with_capacity(images.len());
+        for (image_source, index) in images {
+            match image_source.load_thumbnail(index).await {
+                Ok(thumbnail) => thumbnails.push(thumbnail),
+                Err(error) => Log::handle_error(error),
+            }
+        }
+        ModelRc::new(VecModel::from(thumbnails))
+    }
+
+    /// Show the thumbnails of the first images of a source in its editor,
+    /// an empty id clears them
+    fn on_request_source_thumbnails(&self, id: SharedString) {
+        let image_source = Uuid::from_str(&id).ok().and_then(|uuid| {
+            self.backend
+                .borrow()
+                .image_sources()
+                .get_image_source(uuid)
+                .cloned()
+        });
+        self.app.borrow_mut().thumbnail_source_id =
+            image_source.as_ref().map(ImageSource::id);
+        if let Some(ui) = self.ui.upgrade() {
+            ui.ui().set_edited_source_thumbnails(ModelRc::default());
+        }
+
+        let Some(image_source) = image_source else {
+            return;
+        };
+        let image_count = image_source.check().image_count().min(EDITOR_THUMBNAIL_COUNT);
+        let images = (0..image_count)
+            .map(|index| (image_source.clone(), index))
+            .collect();
+
+        let callback = self.clone();
+        let future = async move {
+            let thumbnails = Self::load_thumbnails(images).await;
+            // Another source may have been opened meanwhile
+            if callback.app.borrow().thumbnail_source_id != Some(image_source.id()) {
+                return;
+            }
+            if let Some(ui) = callback.ui.upgrade() {
+                ui.ui().set_edited_source_thumbnails(thumbnails);
+            }
+        };
+
+        let error = slint::spawn_local(future).map_err(anyhow::Error::from);
+        self.handle_error(error);
+    }
+
+    /// Update the ui with the modifications of the session, and show the thumbnails of
+    /// its images once it is completed
+    fn apply_session_modifications(&self, modifications: &AppBackendModifications) {
+        let Some(ui) = self.ui.upgrade() else {
+            return;
+        };
+        {
+            let backend = self.backend.borrow();
+            ui.update_with_backend_modifications(&backend, modifications);
+        }
+
+        let is_completed = modifications.session().iter().any(|modification| {
+            matches!(
+                modification,
+                SessionModification::State(sg::SessionWindowState::Completed)
+            )
+        });
+        if !is_completed {
+            return;
+        }
+
+        let images = self.app.borrow().session.shown_images();
+        let weak_ui = self.ui.clone();
+        let error = slint::spawn_local(async move {
+            let thumbnails = Self::load_thumbnails(images).await;
+            if let Some(ui) = weak_ui.upgrade() {
+                ui.ui().set_session_review_thumbnails(thumbnails);
+            }
+        })
+        .map_err(anyhow::Error::from);
+        self.handle_error(error);
+    }
+
+    /// Add or update an edited source, save it and check it
+    ///
+    /// `add_or_update` applies the data of the editor to the sources of the backend
+    fn add_or_save_source<F>(&self, add_or_update: F)
+    where
+        F: FnOnce(&mut ImageSourceBackend, &App) -> anyhow::Result<AppBackendModifications>,
+    {
+        fn execute<F>(this: &AppCallback, add_or_update: F) -> anyhow::Result<()>
+        where
+            F: FnOnce(&mut ImageSourceBackend, &App) -> anyhow::Result<AppBackendModifications>,
+        {
             // Save in backend
             let modifications = {
                 let mut backend = this.backend.borrow_mut();
                 let app = this.app.borrow();
-
-                // enable-path-picking
-                #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
-                let path = app.source_folder.edited_path().cloned();
-                #[cfg(target_os = "android")]
-                let path = Some(std::path::PathBuf::from(data.path.as_str()));
-
-                let modifications = backend
-                    .image_sources_mut()
-                    .add_or_update_image_source_from_edit_folder(&data, path)?;
+                let modifications = add_or_update(backend.image_sources_mut(), &app)?;
 
                 backend.save_to_persistence()?;
 
@@ -127,21 +305,23 @@ impl AppCallback {
             };
 
             // propagate change to the ui
-            if let Some(mut ui) = this.ui.upgrade() {
+            if let Some(ui) = this.ui.upgrade() {
                 let backend = this.backend.borrow();
                 ui.update_with_backend_modifications(&backend, &modifications);
             }
 
-            // Trigger a check of the image source
+            // Trigger a check of the image source, web sources download their manifest
             this.trigger_image_source_check_from_modifications(&modifications);
 
             Ok(())
         }
-        let error = execute(self, data);
+        let error = execute(self, add_or_update);
         self.handle_error(error);
+        self.update_watched_image_sources();
     }
 
-    fn get_folder_source_data_from_id(&self, id: SharedString) -> sg::EditSourceFolderData {
+    /// Data of a source for its editor, the default data when it is not of the editor type
+    fn get_source_data_from_id<T: TryFrom<ImageSource> + Default>(&self, id: SharedString) -> T {
         let result = Uuid::from_str(&id)
             .map_err(anyhow::Error::from)
             .and_then(|uuid| {
@@ -158,6 +338,30 @@ impl AppCallback {
             .unwrap_or_default()
     }
 
+    fn add_or_save_folder_source(&self, data: sg::EditSourceFolderData) {
+        self.add_or_save_source(|image_sources, app| {
+            // enable-path-picking
+            #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+            let path = app.source_folder.edited_path().cloned();
+            #[cfg(target_os = "android")]
+            let path = Some(std::path::PathBuf::from(data.path.as_str()));
+
+            image_sources.add_or_update_image_source_from_edit_folder(&data, path)
+        });
+    }
+
+    fn add_or_save_archive_source(&self, data: sg::EditSourceArchiveData) {
+        self.add_or_save_source(|image_sources, app| {
+            // enable-path-picking
+            #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+            let path = app.source_archive.edited_path().cloned();
+            #[cfg(target_os = "android")]
+            let path = Some(std::path::PathBuf::from(data.path.as_str()));
+
+            image_sources.add_or_update_image_source_from_edit_archive(&data, path)
+        });
+    }
+
     fn on_request_asked_path(&self) -> i32 {
         #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
         {
@@ -198,14 +402,168 @@ impl AppCallback {
         }
     }
 
+    /// List the subfolders of the edited folder in the background, the editor is updated
+    /// with the returned id once they are listed
+    fn on_request_subfolders(&self, data: sg::EditSourceFolderData) -> i32 {
+        let id = self.app.borrow().source_folder().next_request_subfolders_id() as i32;
+        let filter = FolderFilter::from(&data);
+        let path = std::path::PathBuf::from(data.path.as_str());
+
+        let callback = self.clone();
+        let future = async move {
+            // A path that can't be listed, like the placeholder of a new folder, has no subfolders
+            let subfolders = callback
+                .handle_error(ImageSourceFolder::list_subfolders(path, filter.clone()).await)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|subfolder| sg::EditSourceFolderSubfolderData {
+                    used: filter.is_subfolder_used(&subfolder.name),
+                    name: subfolder.name.into(),
+                    image_count: subfolder.image_count as i32,
+                })
+                .collect::<Vec<_>>();
+
+            if let Some(ui) = callback.ui.upgrade() {
+                ui.ui().invoke_dispatch_edit_source_folder_request_subfolders_completed(
+                    id,
+                    ModelRc::new(VecModel::from(subfolders)),
+                );
+            }
+        };
+
+        let error = slint::spawn_local(future).map_err(anyhow::Error::from);
+        self.handle_error(error);
+
+        id
+    }
+
+    fn on_request_asked_archive_path(&self) -> i32 {
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+        {
+            use rfd::AsyncFileDialog;
+
+            let app = self.app.borrow();
+            let id = app.source_archive().next_request_ask_path_id() as i32;
+            let ui = self.ui.clone();
+            let app_clone = self.app.clone();
+            let future = async move {
+                if let Some(selection) = AsyncFileDialog::new()
+                    .add_filter("Archive", ArchiveFormat::EXTENSIONS)
+                    .pick_file()
+                    .await
+                {
+                    let mut app_clone_ref = app_clone.borrow_mut();
+                    // try to store the selected path
+                    app_clone_ref
+                        .source_archive_mut()
+                        .set_edited_path(selection.path());
+
+                    // update the ui
+                    ui.upgrade()
+                        .unwrap()
+                        .ui()
+                        .invoke_dispatch_edit_source_archive_request_asked_path_completed(
+                            id,
+                            selection.path().to_string_lossy().to_string().into(),
+                        );
+                }
+            };
+
+            let error = slint::spawn_local(future).map_err(anyhow::Error::from);
+            self.handle_error(error);
+
+            id
+        }
+
+        #[cfg(target_os = "android")]
+        {
+            unreachable!()
+        }
+    }
+
+    fn add_or_save_list_source(&self, data: sg::EditSourceListData) {
+        self.add_or_save_source(|image_sources, app| {
+            // enable-path-picking
+            #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+            let path = app.source_list.edited_path().cloned();
+            #[cfg(target_os = "android")]
+            let path = Some(std::path::PathBuf::from(data.path.as_str()));
+
+            image_sources.add_or_update_image_source_from_edit_list(&data, path)
+        });
+    }
+
+    fn on_request_asked_list_path(&self) -> i32 {
+        #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+        {
+            use rfd::AsyncFileDialog;
+
+            let app = self.app.borrow();
+            let id = app.source_list().next_request_ask_path_id() as i32;
+            let ui = self.ui.clone();
+            let app_clone = self.app.clone();
+            let future = async move {
+                if let Some(selection) = AsyncFileDialog::new()
+                    .add_filter("Image list", ImageSourceList::EXTENSIONS)
+                    .pick_file()
+                    .await
+                {
+                    let mut app_clone_ref = app_clone.borrow_mut();
+                    // try to store the selected path
+                    app_clone_ref
+                        .source_list_mut()
+                        .set_edited_path(selection.path());
+
+                    // update the ui
+                    ui.upgrade()
+                        .unwrap()
+                        .ui()
+                        .invoke_dispatch_edit_source_list_request_asked_path_completed(
+                            id,
+                            selection.path().to_string_lossy().to_string().into(),
+                        );
+                }
+            };
+
+            let error = slint::spawn_local(future).map_err(anyhow::Error::from);
+            self.handle_error(error);
+
+            id
+        }
+
+        #[cfg(target_os = "android")]
+        {
+            unreachable!()
+        }
+    }
+
+    fn add_or_save_web_source(&self, data: sg::EditSourceWebData) {
+        self.add_or_save_source(|image_sources, _| {
+            image_sources.add_or_update_image_source_from_edit_web(&data)
+        });
+    }
+
     pub(crate) fn on_delete_source_id(&self, id: SharedString) {
         fn execute(this: &AppCallback, id: SharedString) -> anyhow::Result<()> {
             let mut backend = this.backend.borrow_mut();
             let uuid = Uuid::from_str(&id)?;
 
+            this.app.borrow_mut().thumbnail_jobs.cancel(uuid);
             if let Some(image_source) = backend.image_sources_mut().remove_image_source(uuid) {
+                // The selector entry is deleted, no need to update it
+                backend.remove_image_source_from_session(image_source.id());
+                backend.save_to_persistence()?;
+                if let Err(error) = image_source.remove_cached_files() {
+                    Log::handle_error(format!(
+                        "Failed to remove the cached files of {}: {}",
+                        image_source.name(),
+                        error
+                    ));
+                }
+
                 let diff = ImageSourceModification::Deleted(image_source.id()).into();
-                let mut ui = this.ui.upgrade().ok_or(anyhow::anyhow!(""))?;
+
+                let ui = this.ui.upgrade().ok_or(anyhow::anyhow!(""))?;
                 ui.update_with_backend_modifications(&backend, &diff);
             }
 
@@ -214,6 +572,7 @@ impl AppCallback {
 
         let error = execute(self, id);
         self.handle_error(error);
+        self.update_watched_image_sources();
     }
 
     fn on_set_image_source_used(&self, id: SharedString, is_used: bool) {
@@ -222,15 +581,19 @@ impl AppCallback {
 
             let modification = {
                 let mut backend = callback.backend.borrow_mut();
-                if is_used {
+                let modification = if is_used {
                     backend.add_image_source_to_session(uuid)
                 } else {
                     backend.remove_image_source_from_session(uuid)
+                };
+                if !modification.is_empty() {
+                    backend.save_to_persistence()?;
                 }
+                modification
             };
 
             if !modification.is_empty() {
-                let mut ui = callback.ui.upgrade().ok_or(anyhow::anyhow!(""))?;
+                let ui = callback.ui.upgrade().ok_or(anyhow::anyhow!(""))?;
                 let backend = callback.backend.borrow();
                 ui.update_with_backend_modifications(&backend, &modification);
             }
@@ -242,15 +605,49 @@ impl AppCallback {
         self.handle_error(error);
     }
 
-    fn on_session_start(&self) {
+    /// Returns `false` when the session could not start
+    fn on_session_start(&self) -> bool {
         fn execute(callback: &AppCallback) -> anyhow::Result<()> {
-            let prepared_session_data = {
+            let (prepared_session_data, window_size) = {
                 let ui = callback.ui.upgrade().ok_or(anyhow::anyhow!(""))?;
-                ui.ui().get_prepared_session_data()
+                (ui.ui().get_prepared_session_data(), ui.ui().window().size())
             };
 
+            // Invalid settings are shown next to the Start button and never saved
+            let plan = SessionPlan::from_prepared_session_data(&prepared_session_data);
+            {
+                let ui = callback.ui.upgrade().ok_or(anyhow::anyhow!(""))?;
+                let mut data = ui.ui().get_prepared_session_data();
+                data.settings_error = plan
+                    .as_ref()
+                    .err()
+                    .map(|error| error.to_string())
+                    .unwrap_or_default()
+                    .into();
+                ui.ui().set_prepared_session_data(data);
+            }
+            let plan = plan?;
+
+            // The start button is disabled meanwhile
+            let is_checking = {
+                let backend = callback.backend.borrow();
+                let is_checking = backend
+                    .session()
+                    .image_source_used()
+                    .into_iter()
+                    .any(|uuid| backend.image_sources().is_checking(*uuid));
+                is_checking
+            };
+            if is_checking {
+                return Err(anyhow::anyhow!("Image sources are still being checked"));
+            }
+
             let image_sources = {
-                let backend_ref = callback.backend.borrow();
+                let mut backend_ref = callback.backend.borrow_mut();
+                // Restored on the next launch
+                backend_ref.set_session_settings((&prepared_session_data).into());
+                backend_ref.save_to_persistence()?;
+
                 backend_ref
                     .used_image_source()
                     .into_iter()
@@ -258,16 +655,24 @@ impl AppCallback {
             };
 
             let session_config = AppSessionConfiguration::new(
-                Duration::from_secs(prepared_session_data.image_duration as u64),
-                prepared_session_data.used_image_count as usize,
+                plan,
                 image_sources,
-            );
+                prepared_session_data.selection_strategy.into(),
+                prepared_session_data.avoid_recent_images,
+                parse_seed(&prepared_session_data.seed),
+            )
+            .with_display_target(DisplayTarget {
+                width: window_size.width,
+                height: window_size.height,
+                quality: prepared_session_data.image_quality.into(),
+            });
 
             {
                 let callback_clone = callback.clone();
                 let callback_clone2 = callback.clone();
                 let callback_clone3 = callback.clone();
                 let callback_clone4 = callback.clone();
+                let callback_clone5 = callback.clone();
                 let mut app_ref = callback.app.borrow_mut();
                 app_ref.session.start_session(
                     &session_config,
@@ -276,36 +681,52 @@ impl AppCallback {
                         ui.ui().set_session_time_left(time_left.as_secs_f32())
                     },
                     move || {
-                        let modifications =  {let mut app = callback_clone2.app.borrow_mut();
-                        app.session.go_to_next_image().unwrap() };
-                        {
-                            let mut ui = callback_clone2.ui.upgrade().unwrap();
-                            let backend = callback_clone2.backend.borrow();
-                            ui.update_with_backend_modifications(&backend, &modifications);
+                        let modifications = callback_clone2.app.borrow_mut().session.go_to_next_image();
+                        if let Some(modifications) = callback_clone2.handle_error(modifications) {
+                            callback_clone2.apply_session_modifications(&modifications);
                         }
                     },
                     move || {
                         let ui = callback_clone3.ui.upgrade().unwrap();
                         ui.ui().set_session_state(sg::SessionWindowState::Loading);
                     },
-                    move |image| {
+                    move |image, image_duration| {
                         let ui = callback_clone4.ui.upgrade().unwrap();
+                        ui.ui().set_session_time_total(image_duration.as_secs() as i32);
+                        ui.ui().set_session_time_left(image_duration.as_secs_f32());
                         ui.ui().invoke_session_show_image(image);
                         ui.ui().set_session_state(sg::SessionWindowState::Running);
                     },
+                    move || {
+                        let modifications = callback_clone5.app.borrow_mut().session.skip_failed_image();
+                        if let Some(modifications) = callback_clone5.handle_error(modifications) {
+                            callback_clone5.apply_session_modifications(&modifications);
+                        }
+                    },
                 )?;
             }
 
             // Update ui with init data
             let ui = callback.ui.upgrade().ok_or(anyhow::anyhow!(""))?;
-            let prepared_session_data = ui.ui().get_prepared_session_data();
-            ui.ui()
-                .set_session_time_left(prepared_session_data.image_duration as f32);
+            ui.ui().set_session_review_thumbnails(ModelRc::default());
+            let image_duration = session_config
+                .plan()
+                .image_duration(0)
+                .unwrap_or_default();
+            ui.ui().set_session_time_total(image_duration.as_secs() as i32);
+            ui.ui().set_session_time_left(image_duration.as_secs_f32());
+            let replay_seed = callback.app.borrow().session.replay_seed();
+            ui.ui().set_session_seed(
+                replay_seed
+                    .map(|seed| seed.to_string())
+                    .unwrap_or_default()
+                    .into(),
+            );
 
             Ok(())
         }
-        let error = execute(&self);
-        self.handle_error(error);
+        let error = execute(self);
+        self.handle_error(error).is_some()
     }
 }
 
@@ -328,16 +749,6 @@ impl App {
     ) -> Result<(), anyhow::Error> {
         let app_callback = AppCallback::new(app, &ui.as_weak(), backend);
 
-        ui.ui().set_prepared_session_data(sg::PreparedSessionData {
-            available_image_count: 0,
-            image_duration: 30,
-            used_image_count: 5,
-            status: sg::StatusIconData {
-                r#type: sg::StatusIconType::Unknown,
-                error: SharedString::default(),
-            },
-        });
-
         #[cfg(any(target_os = "windows", target_os = "linux", target_os = "macos"))]
         ui.ui().set_enable_pick_path(true);
         #[cfg(target_os = "android")]
@@ -346,11 +757,34 @@ impl App {
         let modifications = {
             let mut backend_ref = backend.borrow_mut();
             let modifications = backend_ref.update_from_persistence()?;
-            ui.update_with_backend_modifications(&mut backend_ref, &modifications);
+            // Settings of the last session, or the defaults
+            ui.ui()
+                .set_prepared_session_data(backend_ref.session().settings().into());
+            if let Some(notice) = backend_ref.take_persistence_notice() {
+                ui.ui().set_notice(notice.into());
+            }
+            ui.update_with_backend_modifications(&backend_ref, &modifications);
             modifications
         };
         app_callback.trigger_image_source_check_from_modifications(&modifications);
 
+        match ImageSourceWatcher::new() {
+            Ok((watcher, receiver)) => {
+                app.borrow_mut().image_source_watcher = Some(watcher);
+                app_callback.update_watched_image_sources();
+
+                let callback = app_callback.clone();
+                slint::spawn_local(async move {
+                    while let Some(uuids) =
+                        ImageSourceWatcher::next_modified_sources(&receiver).await
+                    {
+                        callback.trigger_image_source_check(uuids);
+                    }
+                })?;
+            }
+            Err(error) => Log::handle_error(format!("Image sources are not watched: {}", error)),
+        }
+
         Ok(())
     }
 
@@ -373,7 +807,7 @@ impl App {
             ui.ui()
                 .global::<sg::EditSourceFolderNative>()
                 .on_get_folder_source_data_from_id(move |id| -> sg::EditSourceFolderData {
-                    callback.get_folder_source_data_from_id(id)
+                    callback.get_source_data_from_id(id)
                 });
         }
 
@@ -384,6 +818,13 @@ impl App {
                 .on_request_asked_path(move || callback.on_request_asked_path());
         }
 
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceFolderNative>()
+                .on_request_subfolders(move |data| callback.on_request_subfolders(data));
+        }
+
         {
             let callback = app_callback.clone();
             ui.ui()
@@ -393,6 +834,99 @@ impl App {
                 });
         }
 
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceArchiveNative>()
+                .on_add_or_save_archive_source(move |data| {
+                    callback.add_or_save_archive_source(data)
+                });
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceArchiveNative>()
+                .on_get_archive_source_data_from_id(move |id| -> sg::EditSourceArchiveData {
+                    callback.get_source_data_from_id(id)
+                });
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceArchiveNative>()
+                .on_request_asked_path(move || callback.on_request_asked_archive_path());
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceArchiveNative>()
+                .on_clear_source_archive_editor(move || {
+                    callback.app.borrow_mut().source_archive.clear_edited_path();
+                });
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceListNative>()
+                .on_add_or_save_list_source(move |data| {
+                    callback.add_or_save_list_source(data)
+                });
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceListNative>()
+                .on_get_list_source_data_from_id(move |id| -> sg::EditSourceListData {
+                    callback.get_source_data_from_id(id)
+                });
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceListNative>()
+                .on_request_asked_path(move || callback.on_request_asked_list_path());
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceListNative>()
+                .on_clear_source_list_editor(move || {
+                    callback.app.borrow_mut().source_list.clear_edited_path();
+                });
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceWebNative>()
+                .on_add_or_save_web_source(move |data| callback.add_or_save_web_source(data));
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::EditSourceWebNative>()
+                .on_get_web_source_data_from_id(move |id| -> sg::EditSourceWebData {
+                    callback.get_source_data_from_id(id)
+                });
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::ThumbnailNative>()
+                .on_request_source_thumbnails(move |id| {
+                    callback.on_request_source_thumbnails(id)
+                });
+        }
+
         {
             let callback = app_callback.clone();
             ui.ui()
@@ -431,15 +965,22 @@ impl App {
             ui.ui()
                 .global::<sg::SessionNative>()
                 .on_next_image(move || {
-                    let modifications = callback
-                        .app
-                        .borrow_mut()
-                        .session
-                        .go_to_next_image()
-                        .unwrap();
-
-                    let backend = callback.backend.borrow();
-                    callback.ui.upgrade().unwrap().update_with_backend_modifications(&backend, &modifications);
+                    let modifications = callback.app.borrow_mut().session.go_to_next_image();
+                    if let Some(modifications) = callback.handle_error(modifications) {
+                        callback.apply_session_modifications(&modifications);
+                    }
+                });
+        }
+
+        {
+            let callback = app_callback.clone();
+            ui.ui()
+                .global::<sg::SessionNative>()
+                .on_skip_break(move || {
+                    let modifications = callback.app.borrow_mut().session.skip_break();
+                    if let Some(modifications) = callback.handle_error(modifications) {
+                        callback.apply_session_modifications(&modifications);
+                    }
                 });
         }
 
@@ -448,12 +989,8 @@ impl App {
             ui.ui()
                 .global::<sg::SessionNative>()
                 .on_previous_image(move || {
-                    callback
-                        .app
-                        .borrow_mut()
-                        .session
-                        .go_to_previous_image()
-                        .unwrap()
+                    let result = callback.app.borrow_mut().session.go_to_previous_image();
+                    callback.handle_error(result);
                 });
         }
 
@@ -2,12 +2,13 @@ use std::collections::HashSet;
 use std::rc::{Rc, Weak};
 
 use slint::{ComponentHandle, Model, VecModel};
+use uuid::Uuid;
 
 use crate::app::backend::{
     AppBackend, AppBackendModifications, ImageSourceModification, SessionModification,
 };
 use crate::app::image_source::{
-    ImageSource, ImageSourceCheck, ImageSourceStatus, ImageSourceTrait,
+    find_duplicate_images, ImageSource, ImageSourceCheck, ImageSourceStatus, ImageSourceTrait,
 };
 use crate::sg;
 
@@ -81,9 +82,32 @@ impl AppUi {
         self.ui.run()
     }
 
+    /// Display the image source again in the selector, like the time of its last check
+    pub fn update_image_source_selector_entry(&self, backend: &AppBackend, uuid: Uuid) {
+        let uuid_str = uuid.to_string();
+        if let Some(position) = self
+            .backend
+            .image_source_selector_entries
+            .iter()
+            .position(|item| item.id == uuid_str)
+        {
+            let image_source = backend.image_sources().get_image_source(uuid).expect("");
+
+            let mut model = self
+                .backend
+                .image_source_selector_entries
+                .row_data(position)
+                .unwrap();
+            image_source.update_image_source_selector_entry(&mut model);
+            self.backend
+                .image_source_selector_entries
+                .set_row_data(position, model);
+        }
+    }
+
     /// Update the UI based on modification occurred in the backend
     pub fn update_with_backend_modifications(
-        &mut self,
+        &self,
         backend: &AppBackend,
         modifications: &AppBackendModifications,
     ) {
@@ -111,25 +135,7 @@ impl AppUi {
                 );
 
             for uuid in edits {
-                let uuid_str = uuid.to_string();
-                if let Some(position) = self
-                    .backend
-                    .image_source_selector_entries
-                    .iter()
-                    .position(|item| item.id == uuid_str)
-                {
-                    let image_source = backend.image_sources().get_image_source(*uuid).expect("");
-
-                    let mut model = self
-                        .backend
-                        .image_source_selector_entries
-                        .row_data(position)
-                        .unwrap();
-                    image_source.update_image_source_selector_entry(&mut model);
-                    self.backend
-                        .image_source_selector_entries
-                        .set_row_data(position, model);
-                }
+                self.update_image_source_selector_entry(backend, *uuid);
             }
         }
 
@@ -188,7 +194,7 @@ impl AppUi {
                         None
                     }
                 })
-                .last()
+                .next_back()
             {
                 self.ui.set_session_state(*state);
             }
@@ -210,54 +216,97 @@ impl AppUi {
             };
 
             if update {
-                let status = backend
-                    .session()
-                    .image_source_used()
-                    .into_iter()
-                    .filter_map(|uuid| {
-                        backend
-                            .image_sources()
-                            .get_image_source(*uuid)
-                            .map(ImageSource::check)
-                    })
-                    .fold(ImageSourceCheck::default(), |acc, value| {
-                        match (acc.status(), value.status()) {
-                            (ImageSourceStatus::Unknown, _) => value.clone(),
-                            (
-                                ImageSourceStatus::Error(old_error),
-                                ImageSourceStatus::Error(new_error),
-                            ) => ImageSourceCheck::new(
-                                acc.image_count(),
-                                ImageSourceStatus::Error(old_error.clone() + &new_error),
-                            ),
-                            (_, ImageSourceStatus::Error(new_error)) => ImageSourceCheck::new(
-                                acc.image_count(),
-                                ImageSourceStatus::Error(new_error.clone()),
-                            ),
-                            (ImageSourceStatus::Error(old_error), _) => ImageSourceCheck::new(
-                                acc.image_count(),
-                                ImageSourceStatus::Error(old_error.clone()),
-                            ),
-                            (ImageSourceStatus::Valid, ImageSourceStatus::Valid) => {
-                                ImageSourceCheck::new(
-                                    acc.image_count() + value.image_count(),
-                                    ImageSourceStatus::Valid,
-                                )
-                            }
-                            (ImageSourceStatus::Valid, _) => {
-                                ImageSourceCheck::new(acc.image_count(), ImageSourceStatus::Valid)
-                            }
-                        }
-                    });
-
-                let prepared_session_data = self.ui.get_prepared_session_data();
-                self.ui.set_prepared_session_data(sg::PreparedSessionData {
-                    available_image_count: status.image_count() as i32,
-                    used_image_count: prepared_session_data.used_image_count,
-                    status: status.status().into(),
-                    image_duration: prepared_session_data.image_duration,
-                })
+                self.update_prepared_session_data(backend);
             }
         }
     }
+
+    /// Display the status and the image count of the sources used by the session
+    ///
+    /// The session can't start while one of them is being checked
+    pub fn update_prepared_session_data(&self, backend: &AppBackend) {
+        let status = backend
+            .session()
+            .image_source_used()
+            .into_iter()
+            .filter_map(|uuid| {
+                backend
+                    .image_sources()
+                    .get_image_source(*uuid)
+                    .map(ImageSource::check)
+            })
+            .fold(ImageSourceCheck::default(), |acc, value| {
+                match (acc.status(), value.status()) {
+                    (ImageSourceStatus::Unknown, _) => value.clone(),
+                    (
+                        ImageSourceStatus::Error(old_error),
+                        ImageSourceStatus::Error(new_error),
+                    ) => ImageSourceCheck::new(
+                        acc.image_count(),
+                        ImageSourceStatus::Error(old_error.clone() + new_error),
+                    ),
+                    (_, ImageSourceStatus::Error(new_error)) => ImageSourceCheck::new(
+                        acc.image_count(),
+                        ImageSourceStatus::Error(new_error.clone()),
+                    ),
+                    (ImageSourceStatus::Error(old_error), _) => ImageSourceCheck::new(
+                        acc.image_count(),
+                        ImageSourceStatus::Error(old_error.clone()),
+                    ),
+                    (ImageSourceStatus::Valid, ImageSourceStatus::Valid) => {
+                        ImageSourceCheck::new(
+                            acc.image_count() + value.image_count(),
+                            ImageSourceStatus::Valid,
+                        )
+                    }
+                    (ImageSourceStatus::Valid, _) => {
+                        ImageSourceCheck::new(acc.image_count(), ImageSourceStatus::Valid)
+                    }
+                }
+            });
+
+        // Copies are counted once, like the session shows them
+        let duplicate_image_count = find_duplicate_images(
+            backend
+                .session()
+                .image_source_used()
+                .into_iter()
+                .filter_map(|uuid| backend.image_sources().get_image_source(*uuid))
+                .map(ImageSource::check),
+        )
+        .len();
+
+        let is_checking = backend
+            .session()
+            .image_source_used()
+            .into_iter()
+            .any(|uuid| backend.image_sources().is_checking(*uuid));
+        let status_data = match status.status() {
+            ImageSourceStatus::Valid if is_checking => sg::StatusIconData {
+                r#type: sg::StatusIconType::Unknown,
+                error: "Checking the image sources".into(),
+            },
+            status => status.into(),
+        };
+
+        let prepared_session_data = self.ui.get_prepared_session_data();
+        self.ui.set_prepared_session_data(sg::PreparedSessionData {
+            available_image_count: status.image_count().saturating_sub(duplicate_image_count)
+                as i32,
+            duplicate_image_count: duplicate_image_count as i32,
+            used_image_count: prepared_session_data.used_image_count,
+            status: status_data,
+            settings_error: prepared_session_data.settings_error,
+            image_duration: prepared_session_data.image_duration,
+            selection_strategy: prepared_session_data.selection_strategy,
+            avoid_recent_images: prepared_session_data.avoid_recent_images,
+            seed: prepared_session_data.seed,
+            plan: prepared_session_data.plan,
+            custom_plan: prepared_session_data.custom_plan,
+            break_interval: prepared_session_data.break_interval,
+            break_after_segments: prepared_session_data.break_after_segments,
+            break_duration: prepared_session_data.break_duration,
+            image_quality: prepared_session_data.image_quality,
+        })
+    }
 }
@@ -0,0 +1,72 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// File written next to `path`, then renamed over it by [`AsideFile::persist`]
+///
+/// A crash or a full disk never leaves a partial file at `path`. The name is unique
+/// per thread, so two writers of the same file never mix their content.
+/// The temporary file is removed when dropped without being persisted, like on an error.
+pub struct AsideFile {
+    file: File,
+    path: PathBuf,
+    temporary_path: PathBuf,
+    persisted: bool,
+}
+
+impl AsideFile {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(format!(".{:?}.tmp", std::thread::current().id()));
+        let temporary_path = path.with_file_name(file_name);
+
+        Ok(Self {
+            file: File::create(&temporary_path)?,
+            path: path.to_path_buf(),
+            temporary_path,
+            persisted: false,
+        })
+    }
+
+    /// Path of the content written so far, to check it before [`AsideFile::persist`]
+    pub fn temporary_path(&self) -> &Path {
+        &self.temporary_path
+    }
+
+    /// Sync the content to the disk and replace the file at `path` with it
+    pub fn persist(mut self) -> std::io::Result<()> {
+        self.file.sync_all()?;
+        std::fs::rename(&self.temporary_path, &self.path)?;
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Write for AsideFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AsideFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.temporary_path);
+        }
+    }
+}
+
+/// Replace the file at `path` with `content` at once, see [`AsideFile`]
+pub fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut file = AsideFile::create(path)?;
+    file.write_all(content)?;
+    file.persist()
+}
@@ -0,0 +1,133 @@
+use serde_yaml::{Mapping, Value};
+
+/// Version of the state file written by this build
+///
+/// Additive changes keep the version: new fields with `serde(default)` and new variants,
+/// like the source types or the session settings added since version 1, still read
+/// the files written before them.
+/// Bump it with a migration and a fixture when a field is renamed, removed,
+/// or changes meaning.
+pub const STATE_VERSION: u64 = 1;
+
+/// Upgrade a state written by an older version, one version at a time
+///
+/// `MIGRATIONS[n]` upgrades a state from version `n` to version `n + 1`
+const MIGRATIONS: &[fn(&mut Mapping) -> anyhow::Result<()>] = &[migrate_v0_to_v1];
+
+/// The state file was written by a newer version of the application, which it can't read
+#[derive(Debug, thiserror::Error)]
+#[error("State file version {version} was written by a newer version of the application (supports {STATE_VERSION})")]
+pub struct NewerStateVersion {
+    pub version: u64,
+}
+
+/// Upgrade a state file content to [`STATE_VERSION`]
+///
+/// States without version were written before the version was introduced (version 0)
+pub fn migrate_state(mut state: Value) -> anyhow::Result<Value> {
+    let mapping = state
+        .as_mapping_mut()
+        .ok_or(anyhow::anyhow!("State file is not a mapping"))?;
+
+    let version = match mapping.get("version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .ok_or(anyhow::anyhow!("State version {:?} is not a number", version))?,
+    };
+    if version > STATE_VERSION {
+        return Err(NewerStateVersion { version }.into());
+    }
+
+    for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        migration(mapping)?;
+        mapping.insert("version".into(), (from_version as u64 + 1).into());
+    }
+
+    Ok(state)
+}
+
+/// Version 0 only listed the image sources, the used sources and
+/// the session settings were added later
+fn migrate_v0_to_v1(state: &mut Mapping) -> anyhow::Result<()> {
+    if !state.contains_key("image_sources") {
+        state.insert("image_sources".into(), Value::Sequence(Vec::new()));
+    }
+    if !state.contains_key("used_image_sources") {
+        state.insert("used_image_sources".into(), Value::Sequence(Vec::new()));
+    }
+    if !state.contains_key("session_settings") {
+        state.insert("session_settings".into(), Value::Mapping(Mapping::new()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::backend::AppPersistentState;
+    use crate::app::image_source::ImageSourceTrait;
+
+    /// Read a state file content like the application does
+    fn read_state(content: &str) -> anyhow::Result<AppPersistentState> {
+        let state = migrate_state(serde_yaml::from_str(content)?)?;
+        Ok(serde_yaml::from_value(state)?)
+    }
+
+    /// The migrated state is saved and read again without changes
+    fn assert_round_trip(state: &AppPersistentState) {
+        let saved = serde_yaml::to_string(state).unwrap();
+        let read_again = read_state(&saved).unwrap();
+        assert_eq!(
+            serde_yaml::to_value(state).unwrap(),
+            serde_yaml::to_value(&read_again).unwrap()
+        );
+    }
+
+    #[test]
+    fn migrate_v0() {
+        let state = read_state(include_str!("fixtures/state_v0.yml")).unwrap();
+
+        assert_eq!(state.version, STATE_VERSION);
+        let names = state
+            .image_sources
+            .iter()
+            .map(|image_source| image_source.name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Poses", "Animals"]);
+        assert!(state
+            .image_sources
+            .iter()
+            .all(|image_source| image_source.weight() == 1));
+        assert!(state.used_image_sources.is_empty());
+        assert_eq!(state.session_settings, Default::default());
+        assert_round_trip(&state);
+    }
+
+    #[test]
+    fn migrate_v1() {
+        let state = read_state(include_str!("fixtures/state_v1.yml")).unwrap();
+
+        assert_eq!(state.version, STATE_VERSION);
+        let sources = state
+            .image_sources
+            .iter()
+            .map(|image_source| (image_source.name(), image_source.weight()))
+            .collect::<Vec<_>>();
+        assert_eq!(sources, [("Poses", 3), ("Hands", 1)]);
+        assert_eq!(state.used_image_sources, [state.image_sources[0].id()]);
+        let settings = serde_yaml::to_value(&state.session_settings).unwrap();
+        assert_eq!(settings["image_count"], 10);
+        assert_eq!(settings["plan"], "Class");
+        assert_eq!(settings["selection_strategy"], "Weighted");
+        assert_eq!(settings["break_interval"], 5);
+        assert_round_trip(&state);
+    }
+
+    #[test]
+    fn newer_version_is_not_read() {
+        let error = read_state("version: 1000\nimage_sources: []\n").unwrap_err();
+        assert!(error.downcast_ref::<NewerStateVersion>().is_some());
+    }
+}
@@ -3,12 +3,13 @@ use uuid::Uuid;
 pub use modifications::{AppBackendModifications, ImageSourceModification, SessionModification};
 pub use persistence::AppPersistentState;
 use crate::app::image_source::ImageSourceBackend;
-use crate::app::session::SessionBackend;
+use crate::app::session::{SessionBackend, SessionSettings};
 use crate::sg;
 
 use super::image_source::{ImageSource};
 use crate::app::image_source::ImageSourceTrait;
 
+mod migration;
 mod modifications;
 mod persistence;
 
@@ -16,6 +17,10 @@ mod persistence;
 pub struct AppBackend {
     image_sources: ImageSourceBackend,
     session: SessionBackend,
+    /// Message for the user about the persisted state, like a restored backup
+    persistence_notice: Option<String>,
+    /// The state file was written by a newer version, saving would lose its content
+    persistence_read_only: bool,
 }
 
 impl AppBackend {
@@ -23,6 +28,8 @@ impl AppBackend {
         Self {
             image_sources: ImageSourceBackend::new(),
             session: SessionBackend::default(),
+            persistence_notice: None,
+            persistence_read_only: false,
         }
     }
     pub fn image_sources(&self) -> &ImageSourceBackend {
@@ -37,8 +44,19 @@ impl AppBackend {
     }
 
     pub fn update_from_persistence(&mut self) -> anyhow::Result<AppBackendModifications> {
-        if let Some(state) = persistence::AppPersistence::load_state()? {
-            let modifications = self.image_sources.update_from_state(&state)?;
+        let loaded_state = persistence::AppPersistence::load_state()?;
+        self.persistence_notice = loaded_state.notice;
+        self.persistence_read_only = loaded_state.read_only;
+
+        if let Some(state) = loaded_state.state {
+            let mut modifications = self.image_sources.update_from_state(&state)?;
+
+            // Sources deleted since are ignored by add_image_source_to_session
+            for uuid in state.used_image_sources.iter() {
+                modifications.append(self.add_image_source_to_session(*uuid));
+            }
+            self.session.set_settings(state.session_settings);
+
             Ok(modifications)
         }
         else {
@@ -46,8 +64,16 @@ impl AppBackend {
         }
     }
 
+    /// Notice about the last load of the persisted state, taken only once
+    pub fn take_persistence_notice(&mut self) -> Option<String> {
+        self.persistence_notice.take()
+    }
+
     pub fn save_to_persistence(&mut self) -> anyhow::Result<()> {
-        persistence::AppPersistence::save_state(self.image_sources.image_sources())?;
+        if self.persistence_read_only {
+            return Ok(());
+        }
+        persistence::AppPersistence::save_state(self.image_sources.image_sources(), &self.session)?;
 
         Ok(())
     }
@@ -62,6 +88,10 @@ impl AppBackend {
         }
     }
 
+    pub fn set_session_settings(&mut self, settings: SessionSettings) {
+        self.session.set_settings(settings);
+    }
+
     pub fn remove_image_source_from_session(&mut self, uuid: Uuid) -> AppBackendModifications {
         if self.session.remove_image_source(uuid) {
             SessionModification::RemovedImageSource(uuid).into()
@@ -81,7 +111,7 @@ impl AppBackend {
                 image_count: image_source.check().image_count() as i32,
                 name: image_source.name().into(),
                 enabled: self.session().is_image_source_used(image_source.id()),
-                status: image_source.check().status().into(),
+                status: image_source.check().into(),
             })
     }
 
@@ -20,6 +20,12 @@ impl AppBackendModifications {
     pub(crate) fn is_empty(&self) -> bool {
         self.image_sources.is_empty() && self.session.is_empty()
     }
+
+    /// Add the modifications of `other` after the current ones
+    pub(crate) fn append(&mut self, mut other: AppBackendModifications) {
+        self.image_sources.append(&mut other.image_sources);
+        self.session.append(&mut other.session);
+    }
 }
 
 
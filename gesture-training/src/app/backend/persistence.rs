@@ -1,47 +1,209 @@
 use serde::{Serialize, Deserialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use uuid::Uuid;
 
+use crate::app::atomic_file;
+use crate::app::backend::migration;
 use crate::app::image_source::ImageSource;
+use crate::app::log::Log;
+use crate::app::session::{SessionBackend, SessionSettings};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppPersistentState {
+    /// Schema version, see [`migration::STATE_VERSION`]
+    pub version: u64,
     pub image_sources: Vec<ImageSource>,
+    /// Image sources used by the next session
+    #[serde(default)]
+    pub used_image_sources: Vec<Uuid>,
+    #[serde(default)]
+    pub session_settings: SessionSettings,
+}
+
+/// Number of previous state files kept next to the state file
+const BACKUP_COUNT: usize = 5;
+
+/// The backups are rotated by the first save of a launch only,
+/// so they keep the states of the previous launches and not of the last minutes
+static BACKUPS_ROTATED: AtomicBool = AtomicBool::new(false);
+
+/// State read from the state file or, when it is unreadable, from one of its backups
+pub struct LoadedState {
+    pub state: Option<AppPersistentState>,
+    /// Explain to the user why the state file was not used
+    pub notice: Option<String>,
+    /// The state file must not be overwritten, it was written by a newer version
+    pub read_only: bool,
 }
 
 pub struct AppPersistence {}
 
 impl AppPersistence {
-    pub fn load_state() -> anyhow::Result<Option<AppPersistentState>> {
+    /// Read the state file, falling back to the newest backup that can be read
+    ///
+    /// An unreadable state file is moved aside so the next saves don't rotate it into the backups.
+    /// A state file written by a newer version is left in place and the state is read only.
+    pub fn load_state() -> anyhow::Result<LoadedState> {
         let path = Self::state_file();
 
-        if path.exists() && path.is_file() {
-            let content = std::fs::read_to_string(&path)?;
-            let state: AppPersistentState = serde_yaml::from_str(&content)?;
-            Ok(Some(state))
+        if !(path.exists() && path.is_file()) {
+            return Ok(LoadedState {
+                state: None,
+                notice: None,
+                read_only: false,
+            });
         }
-        else {
-            Ok(None)
+
+        let error = match Self::read_state(&path) {
+            Ok(state) => {
+                return Ok(LoadedState {
+                    state: Some(state),
+                    notice: None,
+                    read_only: false,
+                })
+            }
+            Err(error) => error,
+        };
+        Log::handle_error(format!("Failed to read {}: {}", path.display(), error));
+
+        // Readable by the version that wrote it, it is not corrupted and must be kept as is
+        if let Some(newer) = error.downcast_ref::<migration::NewerStateVersion>() {
+            return Ok(LoadedState {
+                state: None,
+                notice: Some(format!(
+                    "The saved state {} was written by a newer version of the application \
+                     (state version {}, this version reads up to {}). \
+                     It was left untouched and changes made now will not be saved.",
+                    path.display(),
+                    newer.version,
+                    migration::STATE_VERSION
+                )),
+                read_only: true,
+            });
         }
+
+        let corrupted_path = path.with_extension("yml.corrupted");
+        // Still worth restoring a backup, the unreadable file is overwritten by the next save
+        let kept_notice = match std::fs::rename(&path, &corrupted_path) {
+            Ok(()) => format!("The unreadable file was kept as {}.", corrupted_path.display()),
+            Err(rename_error) => {
+                Log::handle_error(format!(
+                    "Failed to move {} to {}: {}",
+                    path.display(),
+                    corrupted_path.display(),
+                    rename_error
+                ));
+                "The unreadable file could not be kept and will be replaced.".to_string()
+            }
+        };
+
+        let backup = (1..=BACKUP_COUNT)
+            .map(|index| Self::backup_file(&path, index))
+            .filter(|backup_path| backup_path.is_file())
+            .find_map(|backup_path| match Self::read_state(&backup_path) {
+                Ok(state) => Some((backup_path, state)),
+                Err(error) => {
+                    Log::handle_error(format!(
+                        "Failed to read {}: {}",
+                        backup_path.display(),
+                        error
+                    ));
+                    None
+                }
+            });
+
+        Ok(match backup {
+            Some((backup_path, state)) => LoadedState {
+                state: Some(state),
+                notice: Some(format!(
+                    "The saved state could not be read ({}), it was restored from the backup {}. {}",
+                    error,
+                    backup_path.display(),
+                    kept_notice
+                )),
+                read_only: false,
+            },
+            None => LoadedState {
+                state: None,
+                notice: Some(format!(
+                    "The saved state could not be read ({}) and no backup could be restored. {}",
+                    error,
+                    kept_notice
+                )),
+                read_only: false,
+            },
+        })
     }
 
-    pub fn save_state<'a>(image_sources: impl IntoIterator<Item=&'a ImageSource>) -> anyhow::Result<()> {
+    fn read_state(path: &Path) -> anyhow::Result<AppPersistentState> {
+        let content = std::fs::read_to_string(path)?;
+        let state = migration::migrate_state(serde_yaml::from_str(&content)?)?;
+        Ok(serde_yaml::from_value(state)?)
+    }
+
+    pub fn save_state<'a>(
+        image_sources: impl IntoIterator<Item = &'a ImageSource>,
+        session: &SessionBackend,
+    ) -> anyhow::Result<()> {
         let path = Self::state_file();
 
+        let mut used_image_sources = session
+            .image_source_used()
+            .into_iter()
+            .copied()
+            .collect::<Vec<_>>();
+        // Keep the file stable between saves
+        used_image_sources.sort();
+
         let state = AppPersistentState {
+            version: migration::STATE_VERSION,
             image_sources: image_sources.into_iter().cloned().collect(),
+            used_image_sources,
+            session_settings: session.settings().clone(),
         };
 
         let serialized = serde_yaml::to_string(&state)?;
 
-        if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(&parent)?;
+        // A failed backup is tried again by the next save, it never prevents saving
+        if path.is_file() && !BACKUPS_ROTATED.load(Ordering::Relaxed) {
+            match Self::rotate_backups(&path) {
+                Ok(()) => BACKUPS_ROTATED.store(true, Ordering::Relaxed),
+                Err(error) => Log::handle_error(format!(
+                    "Failed to back up {}: {}",
+                    path.display(),
+                    error
+                )),
+            }
         }
 
-        std::fs::write(&path, &serialized)?;
+        // Written aside then renamed, a crash or a full disk never leaves a partial state file
+        atomic_file::write_atomically(&path, serialized.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Shift the backups by one and copy the current state file as the newest backup
+    ///
+    /// Called once per launch, see [`BACKUPS_ROTATED`]
+    fn rotate_backups(path: &Path) -> anyhow::Result<()> {
+        for index in (1..BACKUP_COUNT).rev() {
+            let backup_path = Self::backup_file(path, index);
+            if backup_path.is_file() {
+                std::fs::rename(&backup_path, Self::backup_file(path, index + 1))?;
+            }
+        }
+        // Copied and not renamed, the state file must exist until it is replaced
+        std::fs::copy(path, Self::backup_file(path, 1))?;
 
         Ok(())
     }
 
+    /// `index` starts at 1 for the newest backup
+    fn backup_file(path: &Path, index: usize) -> PathBuf {
+        path.with_extension(format!("yml.{}", index))
+    }
+
     fn state_file() -> PathBuf {
         #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
         {
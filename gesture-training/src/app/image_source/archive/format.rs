@@ -0,0 +1,676 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::read::GzDecoder;
+use zip::ZipArchive;
+
+use crate::app::atomic_file::AsideFile;
+use crate::app::image_source::decoder::{self, FileKind, SkippedFiles};
+use crate::app::image_source::duplicate::{self, ContentHash, HashCache};
+use crate::app::image_source::index::{FileStamp, ImageIndex};
+use crate::app::image_source::thumbnail::ThumbnailKey;
+use crate::app::image_source::{cache_directory, evict_least_recently_used};
+use crate::app::log::Log;
+use crate::app::stable_hash::stable_hash;
+
+/// Container formats supported by archive image sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+}
+
+/// Zip archive opened by [`ArchiveFormat::build_index`], shared by its entries
+/// so the central directory is not parsed again to read each image
+type SharedZipArchive = Arc<Mutex<ZipArchive<File>>>;
+
+/// Where the data of an image is stored inside its archive
+#[derive(Debug, Clone)]
+pub enum ArchiveEntryLocation {
+    /// Index of the entry in the zip central directory
+    Zip {
+        index: usize,
+        archive: SharedZipArchive,
+    },
+    /// Offset of the entry data in the (uncompressed) tar stream
+    Tar { offset: u64, size: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    name: String,
+    location: ArchiveEntryLocation,
+}
+
+impl ArchiveEntry {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl ArchiveFormat {
+    /// Extensions used by the file picker
+    ///
+    /// `gz` and `zst` let the picker show `.tar.gz` and `.tar.zst` files,
+    /// other compressed files are not archives, see [`ArchiveFormat::from_path`]
+    pub const EXTENSIONS: &'static [&'static str] =
+        &["zip", "cbz", "tar", "cbt", "gz", "tgz", "zst", "tzst"];
+
+    /// Format from the name of the archive
+    ///
+    /// A compressed file is a tar stream only when named like one (`.tar.gz`, `.tgz`, ...),
+    /// a single compressed file like `image.png.gz` is not an archive
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?.to_lowercase();
+        if file_name.ends_with(".zip") || file_name.ends_with(".cbz") {
+            Some(Self::Zip)
+        } else if file_name.ends_with(".tar") || file_name.ends_with(".cbt") {
+            Some(Self::Tar)
+        } else if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if file_name.ends_with(".tar.zst") || file_name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else {
+            None
+        }
+    }
+
+    /// Entries created by macOS archivers (resource forks) use image extensions
+    /// but are not images, they are ignored like directories
+    fn is_ignored_entry(name: &str) -> bool {
+        let is_resource_fork = name.starts_with("__MACOSX/")
+            || Path::new(name)
+                .file_name()
+                .and_then(|file_name| file_name.to_str())
+                .is_some_and(|file_name| file_name.starts_with("._"));
+
+        name.ends_with('/') || is_resource_fork
+    }
+
+    /// Recognize an entry from the start of its content
+    fn classify_entry(name: &str, content: impl Read) -> FileKind {
+        match decoder::read_header(content) {
+            Ok(header) => decoder::classify_file(name, &header),
+            Err(error) => {
+                Log::handle_error(format!("Failed to read {}: {}", name, error));
+                FileKind::Undecodable
+            }
+        }
+    }
+
+    /// Open the tar stream, decompressing it if needed
+    fn open_tar_stream(self, file: File) -> anyhow::Result<Box<dyn Read>> {
+        let file = BufReader::new(file);
+        Ok(match self {
+            ArchiveFormat::Tar => Box::new(file),
+            ArchiveFormat::TarGz => Box::new(GzDecoder::new(file)),
+            ArchiveFormat::TarZst => Box::new(zstd::stream::read::Decoder::with_buffer(file)?),
+            ArchiveFormat::Zip => unreachable!("zip archives are not tar streams"),
+        })
+    }
+
+    /// Read the whole archive once to list its images, sorted by entry name
+    ///
+    /// Entries are recognized from their content, the other ones are counted as skipped
+    pub fn build_index(
+        self,
+        path: &Path,
+    ) -> anyhow::Result<(ImageIndex<ArchiveEntry>, SkippedFiles)> {
+        let stamps = vec![FileStamp::new(path)];
+        let file = File::open(path)?;
+        let mut skipped_files = SkippedFiles::default();
+
+        let mut entries = match self {
+            ArchiveFormat::Zip => {
+                let mut archive = ZipArchive::new(file)?;
+                let mut images = Vec::new();
+                for index in 0..archive.len() {
+                    let (name, kind) = match archive.by_index(index) {
+                        Ok(entry) => {
+                            let name = entry.name().to_string();
+                            if Self::is_ignored_entry(&name) {
+                                continue;
+                            }
+                            let kind = Self::classify_entry(&name, entry);
+                            (name, kind)
+                        }
+                        // Like an encrypted entry
+                        Err(error) => {
+                            Log::handle_error(format!("Failed to read entry {}: {}", index, error));
+                            skipped_files.count(FileKind::Undecodable);
+                            continue;
+                        }
+                    };
+                    match kind {
+                        FileKind::Image => images.push((name, index)),
+                        kind => skipped_files.count(kind),
+                    }
+                }
+
+                let archive = Arc::new(Mutex::new(archive));
+                images
+                    .into_iter()
+                    .map(|(name, index)| ArchiveEntry {
+                        name,
+                        location: ArchiveEntryLocation::Zip {
+                            index,
+                            archive: archive.clone(),
+                        },
+                    })
+                    .collect()
+            }
+            ArchiveFormat::Tar | ArchiveFormat::TarGz | ArchiveFormat::TarZst => {
+                match self.open_uncompressed_tar(path)? {
+                    Some(file) => Self::list_tar_entries(
+                        &mut tar::Archive::new(BufReader::new(file)),
+                        &mut skipped_files,
+                    )?,
+                    // Compressed streams are copied uncompressed to the cache while they are read
+                    None => {
+                        let cache = TarStreamCache::new(path)?;
+                        let mut archive =
+                            tar::Archive::new(cache.start_copy(self.open_tar_stream(file)?)?);
+                        let entries = Self::list_tar_entries(&mut archive, &mut skipped_files)?;
+                        // Without the copy, entries are read from the compressed stream
+                        if let Err(error) = cache.finish_copy(archive.into_inner()) {
+                            Log::handle_error(format!(
+                                "Failed to cache the content of {}: {}",
+                                path.display(),
+                                error
+                            ));
+                        }
+                        entries
+                    }
+                }
+            }
+        };
+        entries.sort_by(|left, right| left.name.cmp(&right.name));
+
+        Ok((ImageIndex::new(path, entries, stamps), skipped_files))
+    }
+
+    /// Images of a tar stream in the order of the stream
+    fn list_tar_entries(
+        archive: &mut tar::Archive<impl Read>,
+        skipped_files: &mut SkippedFiles,
+    ) -> anyhow::Result<Vec<ArchiveEntry>> {
+        let mut entries = Vec::new();
+        for entry in archive.entries()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().to_string();
+            if Self::is_ignored_entry(&name) {
+                continue;
+            }
+            let location = ArchiveEntryLocation::Tar {
+                offset: entry.raw_file_position(),
+                size: entry.size(),
+            };
+            match Self::classify_entry(&name, entry) {
+                FileKind::Image => entries.push(ArchiveEntry { name, location }),
+                kind => skipped_files.count(kind),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Hash the content of the entries found by [`ArchiveFormat::build_index`]
+    ///
+    /// Only the entries missing from `cache` are read, tar streams at most once
+    pub fn hash_entries(
+        self,
+        path: &Path,
+        entries: &[ArchiveEntry],
+        cache: &mut HashCache,
+    ) -> anyhow::Result<Vec<Option<ContentHash>>> {
+        let archive_key = ThumbnailKey::from_path(path)?;
+        let keys = entries
+            .iter()
+            .map(|entry| archive_key.clone().with_entry(&entry.name))
+            .collect::<Vec<_>>();
+        let mut hashes = keys.iter().map(|key| cache.get(key)).collect::<Vec<_>>();
+        let missing = hashes
+            .iter()
+            .enumerate()
+            .filter(|(_, hash)| hash.is_none())
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(hashes);
+        }
+
+        let hash_entry = |name: &str, content: &mut dyn Read| {
+            duplicate::hash_content(content)
+                .map_err(|error| Log::handle_error(format!("Failed to read {}: {}", name, error)))
+                .ok()
+        };
+
+        match self {
+            ArchiveFormat::Zip => {
+                for index in missing {
+                    let entry = &entries[index];
+                    hashes[index] = match &entry.location {
+                        ArchiveEntryLocation::Zip {
+                            index: zip_index,
+                            archive,
+                        } => match archive
+                            .lock()
+                            .unwrap_or_else(|error| error.into_inner())
+                            .by_index(*zip_index)
+                        {
+                            Ok(mut content) => hash_entry(&entry.name, &mut content),
+                            Err(error) => {
+                                Log::handle_error(&error);
+                                None
+                            }
+                        },
+                        ArchiveEntryLocation::Tar { .. } => None,
+                    };
+                }
+            }
+            ArchiveFormat::Tar | ArchiveFormat::TarGz | ArchiveFormat::TarZst => {
+                if let Some(mut file) = self.open_uncompressed_tar(path)? {
+                    for index in missing {
+                        let entry = &entries[index];
+                        hashes[index] = match entry.location {
+                            ArchiveEntryLocation::Tar { offset, size } => {
+                                match file.seek(SeekFrom::Start(offset)) {
+                                    Ok(_) => hash_entry(&entry.name, &mut (&mut file).take(size)),
+                                    Err(error) => {
+                                        Log::handle_error(&error);
+                                        None
+                                    }
+                                }
+                            }
+                            ArchiveEntryLocation::Zip { .. } => None,
+                        };
+                    }
+                } else {
+                    // Entries are sorted by name, not by position in the stream
+                    let mut missing_by_offset = missing
+                        .into_iter()
+                        .filter_map(|index| match entries[index].location {
+                            ArchiveEntryLocation::Tar { offset, .. } => Some((offset, index)),
+                            ArchiveEntryLocation::Zip { .. } => None,
+                        })
+                        .collect::<HashMap<_, _>>();
+                    let mut archive = tar::Archive::new(self.open_tar_stream(File::open(path)?)?);
+                    for entry in archive.entries()? {
+                        if missing_by_offset.is_empty() {
+                            break;
+                        }
+                        let mut entry = entry?;
+                        if let Some(index) = missing_by_offset.remove(&entry.raw_file_position()) {
+                            hashes[index] = hash_entry(&entries[index].name, &mut entry);
+                        }
+                    }
+                }
+            }
+        }
+
+        for (key, hash) in keys.iter().zip(hashes.iter()) {
+            if let Some(hash) = hash {
+                cache.insert(key, *hash);
+            }
+        }
+        Ok(hashes)
+    }
+
+    /// Tar file whose entries can be read directly: the archive itself for plain tar files,
+    /// the uncompressed copy in the cache for compressed tar streams
+    ///
+    /// `None` for compressed streams until [`ArchiveFormat::build_index`] copied them
+    fn open_uncompressed_tar(self, path: &Path) -> anyhow::Result<Option<File>> {
+        Ok(match self {
+            ArchiveFormat::Tar => Some(File::open(path)?),
+            ArchiveFormat::TarGz | ArchiveFormat::TarZst => {
+                let cache = TarStreamCache::new(path)?;
+                if cache.is_up_to_date() {
+                    Some(cache.open()?)
+                } else {
+                    None
+                }
+            }
+            ArchiveFormat::Zip => None,
+        })
+    }
+
+    /// Remove the files created in the cache directory for the archive,
+    /// like the uncompressed copy of a compressed tar stream
+    pub fn remove_cached_files(self, path: &Path) -> std::io::Result<()> {
+        match self {
+            ArchiveFormat::TarGz | ArchiveFormat::TarZst => TarStreamCache::remove(path),
+            ArchiveFormat::Zip | ArchiveFormat::Tar => Ok(()),
+        }
+    }
+
+    /// Read the data of an entry found by [`ArchiveFormat::build_index`]
+    ///
+    /// Zip entries are read from the archive opened by the index.
+    /// Tar entries are read straight from their offset, in the uncompressed copy
+    /// of compressed streams. When that copy was removed from the cache,
+    /// the stream is decompressed up to the entry.
+    pub fn read_entry(self, path: &Path, entry: &ArchiveEntry) -> anyhow::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        match (self, &entry.location) {
+            (ArchiveFormat::Zip, ArchiveEntryLocation::Zip { index, archive }) => {
+                archive
+                    .lock()
+                    .unwrap_or_else(|error| error.into_inner())
+                    .by_index(*index)?
+                    .read_to_end(&mut bytes)?;
+            }
+            (
+                ArchiveFormat::Tar | ArchiveFormat::TarGz | ArchiveFormat::TarZst,
+                ArchiveEntryLocation::Tar { offset, size },
+            ) => match self.open_uncompressed_tar(path)? {
+                Some(mut file) => {
+                    file.seek(SeekFrom::Start(*offset))?;
+                    file.take(*size).read_to_end(&mut bytes)?;
+                }
+                None => {
+                    let mut stream = self.open_tar_stream(File::open(path)?)?;
+                    std::io::copy(&mut stream.by_ref().take(*offset), &mut std::io::sink())?;
+                    stream.take(*size).read_to_end(&mut bytes)?;
+                }
+            },
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Entry {} does not match the archive format",
+                    entry.name
+                ))
+            }
+        }
+
+        if let ArchiveEntryLocation::Tar { size, .. } = entry.location {
+            if bytes.len() as u64 != size {
+                return Err(anyhow::anyhow!("Archive entry {} is truncated", entry.name));
+            }
+        }
+
+        Ok(bytes)
+    }
+}
+
+/// Disk space used by the uncompressed copies of compressed tar streams,
+/// the copies used the longest time ago are removed beyond it
+const TAR_STREAM_CACHE_BUDGET: u64 = 4 * 1024 * 1024 * 1024;
+
+/// Uncompressed copy of a compressed tar stream, kept in the cache directory
+///
+/// Written once while the stream is indexed, so entries are read from their offset
+/// instead of decompressing the stream up to each of them.
+/// Replaced when the archive changes, removed with its source or when the copies
+/// exceed [`TAR_STREAM_CACHE_BUDGET`].
+struct TarStreamCache {
+    path: PathBuf,
+    /// Modification date and size of the archive the copy was made from
+    stamp_path: PathBuf,
+    stamp: String,
+}
+
+impl TarStreamCache {
+    fn new(archive_path: &Path) -> anyhow::Result<Self> {
+        let metadata = std::fs::metadata(archive_path)?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_nanos())
+            .unwrap_or_default();
+
+        let (path, stamp_path) = Self::paths(archive_path);
+        Ok(Self {
+            path,
+            stamp_path,
+            stamp: format!("{} {}", modified, metadata.len()),
+        })
+    }
+
+    fn directory() -> PathBuf {
+        cache_directory().join("archives")
+    }
+
+    /// Paths of the copy and of its stamp
+    fn paths(archive_path: &Path) -> (PathBuf, PathBuf) {
+        let name = format!(
+            "{:016x}",
+            stable_hash(archive_path.to_string_lossy().as_bytes())
+        );
+        let directory = Self::directory();
+        (
+            directory.join(format!("{}.tar", name)),
+            directory.join(format!("{}.stamp", name)),
+        )
+    }
+
+    /// Remove the copy of an archive, if any
+    fn remove(archive_path: &Path) -> std::io::Result<()> {
+        let (path, stamp_path) = Self::paths(archive_path);
+        Self::remove_copy(&path, &stamp_path)
+    }
+
+    fn remove_copy(path: &Path, stamp_path: &Path) -> std::io::Result<()> {
+        for path in [stamp_path, path] {
+            match std::fs::remove_file(path) {
+                Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn is_up_to_date(&self) -> bool {
+        self.path.is_file()
+            && std::fs::read_to_string(&self.stamp_path).is_ok_and(|stamp| stamp == self.stamp)
+    }
+
+    /// Open the copy to read its entries
+    ///
+    /// Its modification date tells when it was last used, see [`TarStreamCache::evict`]
+    fn open(&self) -> std::io::Result<File> {
+        let file = File::options().read(true).write(true).open(&self.path)?;
+        file.set_modified(SystemTime::now())?;
+        Ok(file)
+    }
+
+    /// Copy everything read from `stream` aside, until [`TarStreamCache::finish_copy`]
+    ///
+    /// A check and a session may copy the same stream, each writes its own file
+    fn start_copy<R: Read>(&self, stream: R) -> anyhow::Result<TeeReader<R, BufWriter<AsideFile>>> {
+        // The previous copy is outdated from now on
+        let _ = std::fs::remove_file(&self.stamp_path);
+        Ok(TeeReader {
+            reader: stream,
+            writer: BufWriter::new(AsideFile::create(&self.path)?),
+        })
+    }
+
+    /// Copy the rest of the stream and replace the previous copy
+    ///
+    /// The partial copy is removed on failure
+    fn finish_copy<R: Read>(
+        self,
+        mut stream: TeeReader<R, BufWriter<AsideFile>>,
+    ) -> anyhow::Result<()> {
+        std::io::copy(&mut stream, &mut std::io::sink())?;
+        stream
+            .writer
+            .into_inner()
+            .map_err(|error| error.into_error())?
+            .persist()?;
+        std::fs::write(&self.stamp_path, &self.stamp)?;
+
+        if let Err(error) = Self::evict(&Self::directory(), TAR_STREAM_CACHE_BUDGET) {
+            Log::handle_error(format!("Failed to clean the archive cache: {}", error));
+        }
+        Ok(())
+    }
+
+    /// Remove the copies used the longest time ago until they fit in `budget`
+    ///
+    /// A copy larger than the budget is removed too, its entries are read from the stream
+    fn evict(directory: &Path, budget: u64) -> std::io::Result<()> {
+        evict_least_recently_used(directory, "tar", budget, |path| {
+            Self::remove_copy(path, &path.with_extension("stamp"))
+        })
+    }
+}
+
+/// Write everything read from `reader` to `writer`
+struct TeeReader<R, W> {
+    reader: R,
+    writer: W,
+}
+
+impl<R: Read, W: Write> Read for TeeReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let length = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..length])?;
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Smallest valid image, `seed` makes each image different
+    fn png(seed: u8) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::RgbaImage::from_pixel(1, 1, image::Rgba([seed, 0, 0, 255]))
+            .write_to(
+                &mut std::io::Cursor::new(&mut bytes),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        bytes
+    }
+
+    fn write_tar(path: &Path, files: &[(&str, &[u8])]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *content).unwrap();
+        }
+        builder.finish().unwrap();
+    }
+
+    #[test]
+    fn format_from_path() {
+        for (name, format) in [
+            ("images.CBZ", Some(ArchiveFormat::Zip)),
+            ("images.cbt", Some(ArchiveFormat::Tar)),
+            ("images.tar.gz", Some(ArchiveFormat::TarGz)),
+            ("images.tgz", Some(ArchiveFormat::TarGz)),
+            ("images.tar.zst", Some(ArchiveFormat::TarZst)),
+            ("image.png.gz", None),
+            ("notes.zst", None),
+            ("image.png", None),
+        ] {
+            assert_eq!(ArchiveFormat::from_path(Path::new(name)), format, "{name}");
+        }
+    }
+
+    #[test]
+    fn tar_entries_are_read_from_their_offset() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("images.tar");
+        let (first, second) = (png(1), png(2));
+        write_tar(
+            &path,
+            &[
+                ("b.png", &second),
+                ("notes.txt", b"not an image"),
+                ("__MACOSX/._a.png", b"resource fork"),
+                ("a.png", &first),
+            ],
+        );
+
+        let (index, skipped_files) = ArchiveFormat::Tar.build_index(&path).unwrap();
+        assert_eq!(skipped_files.other, 1);
+        let names = index
+            .entries()
+            .iter()
+            .map(ArchiveEntry::name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["a.png", "b.png"]);
+
+        for (position, content) in [first, second].iter().enumerate() {
+            let entry = index.get(position).unwrap();
+            assert_eq!(
+                &ArchiveFormat::Tar.read_entry(&path, entry).unwrap(),
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn truncated_tar_entries_are_errors() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("images.tar");
+        write_tar(&path, &[("a.png", &png(1))]);
+        let (index, _) = ArchiveFormat::Tar.build_index(&path).unwrap();
+
+        let file = File::options().write(true).open(&path).unwrap();
+        // The data of the entry starts after its 512 bytes header
+        file.set_len(520).unwrap();
+        assert!(ArchiveFormat::Tar
+            .read_entry(&path, index.get(0).unwrap())
+            .is_err());
+    }
+
+    #[test]
+    fn least_recently_used_copies_are_evicted() {
+        let directory = TempDir::new().unwrap();
+        let now = SystemTime::now();
+        // Oldest first
+        for (age, name) in [(30, "old"), (20, "used"), (10, "new")] {
+            let path = directory.path().join(format!("{name}.tar"));
+            std::fs::write(&path, [0; 100]).unwrap();
+            std::fs::write(path.with_extension("stamp"), "stamp").unwrap();
+            File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(now - Duration::from_secs(age))
+                .unwrap();
+        }
+        // Opening a copy marks it as used
+        File::options()
+            .write(true)
+            .open(directory.path().join("used.tar"))
+            .unwrap()
+            .set_modified(now)
+            .unwrap();
+
+        TarStreamCache::evict(directory.path(), 250).unwrap();
+
+        let exists = |name: &str| directory.path().join(name).is_file();
+        assert!(!exists("old.tar") && !exists("old.stamp"));
+        assert!(exists("used.tar") && exists("used.stamp"));
+        assert!(exists("new.tar") && exists("new.stamp"));
+
+        TarStreamCache::evict(directory.path(), 150).unwrap();
+        assert!(exists("used.tar"));
+        assert!(!exists("new.tar"));
+
+        TarStreamCache::evict(directory.path(), 50).unwrap();
+        assert!(!exists("used.tar"));
+    }
+}
@@ -0,0 +1,190 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use async_std::task;
+use serde::{Deserialize, Serialize};
+use slint::{Image, SharedString};
+use uuid::Uuid;
+
+use crate::app::image_source::archive::format::{ArchiveEntry, ArchiveFormat};
+use crate::app::image_source::decoder::{self, DecodedImage, SkippedFiles};
+use crate::app::image_source::duplicate::HashCache;
+use crate::app::image_source::index::{ImageIndex, ImageIndexCache};
+use crate::app::image_source::thumbnail::{ThumbnailCache, ThumbnailKey, ThumbnailSource};
+use crate::app::image_source::{
+    default_weight, DisplayTarget, ImageSource, ImageSourceCheck, ImageSourceStatus, ImageSourceTrait,
+};
+use crate::sg;
+
+/// Use an archive (.zip, .cbz, .tar, .tar.gz, .tar.zst) as an image source
+/// Images are read and decoded in memory, the archive is never extracted on disk.
+/// Compressed tar streams are decompressed once in the cache directory to read their entries directly.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageSourceArchive {
+    id: Uuid,
+    pub(crate) name: String,
+    pub(crate) path: PathBuf,
+    /// Relative chance to pick this source with the weighted selection strategy
+    #[serde(default = "default_weight")]
+    pub(crate) weight: u32,
+    /// Last check, displayed until the source is checked again
+    #[serde(default)]
+    check: ImageSourceCheck,
+    #[serde(skip)]
+    index: ImageIndexCache<ArchiveEntry>,
+}
+
+impl ImageSourceArchive {
+    pub fn new(id: Uuid, name: String, path: PathBuf, check: ImageSourceCheck) -> Self {
+        Self {
+            id,
+            name,
+            path,
+            weight: default_weight(),
+            check,
+            index: ImageIndexCache::default(),
+        }
+    }
+
+    fn format(path: &Path) -> anyhow::Result<ArchiveFormat> {
+        ArchiveFormat::from_path(path)
+            .ok_or(anyhow::anyhow!("Unsupported archive {}", path.display()))
+    }
+
+    fn build_index(&self) -> anyhow::Result<(ImageIndex<ArchiveEntry>, SkippedFiles)> {
+        Self::format(&self.path)?.build_index(&self.path)
+    }
+
+    /// Blocking part of [`ImageSourceTrait::check_source`]
+    fn scan(&self) -> ImageSourceCheck {
+        self.build_index()
+            .and_then(|(index, skipped_files)| {
+                let index = Arc::new(index);
+                self.index.set(index.clone());
+                let format = Self::format(&self.path)?;
+                let mut hash_cache = HashCache::load(&self.path);
+                let content_hashes =
+                    format.hash_entries(&self.path, index.entries(), &mut hash_cache)?;
+                hash_cache.save();
+                Ok(ImageSourceCheck::new(index.len(), ImageSourceStatus::Valid)
+                    .with_skipped_files(skipped_files)
+                    .with_content_hashes(content_hashes))
+            })
+            .unwrap_or_else(|error| {
+                ImageSourceCheck::new(0, ImageSourceStatus::Error(error.to_string()))
+            })
+    }
+
+    /// Remove the files created in the cache directory, once the source is deleted
+    pub fn remove_cached_files(&self) -> anyhow::Result<()> {
+        Ok(Self::format(&self.path)?.remove_cached_files(&self.path)?)
+    }
+
+    fn image_index(&self) -> anyhow::Result<Arc<ImageIndex<ArchiveEntry>>> {
+        self.index
+            .get_or_build(&self.path, || self.build_index().map(|(index, _)| index))
+    }
+
+    fn image_entry(&self, index: usize) -> anyhow::Result<ArchiveEntry> {
+        self.image_index()?
+            .get(index)
+            .cloned()
+            .ok_or(anyhow::anyhow!("No image at index {} in archive", index))
+    }
+}
+
+impl ThumbnailSource for ImageSourceArchive {
+    fn image_count(&self) -> anyhow::Result<usize> {
+        Ok(self.image_index()?.len())
+    }
+
+    /// Any change of the archive creates all its thumbnails again
+    fn thumbnail_key(&self, index: usize) -> anyhow::Result<ThumbnailKey> {
+        let entry = self.image_entry(index)?;
+        Ok(ThumbnailKey::from_path(&self.path)?.with_entry(entry.name()))
+    }
+
+    /// Blocking part of [`ImageSourceTrait::load_image`]
+    fn decode_image(&self, index: usize, target: &DisplayTarget) -> anyhow::Result<DecodedImage> {
+        let entry = self.image_entry(index)?;
+        let bytes = Self::format(&self.path)?.read_entry(&self.path, &entry)?;
+        decoder::decode_image_from_memory(&bytes, target)
+    }
+}
+
+impl ImageSourceTrait for ImageSourceArchive {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    fn check(&self) -> &ImageSourceCheck {
+        &self.check
+    }
+
+    fn set_check(&mut self, check: ImageSourceCheck) {
+        self.check = check;
+    }
+
+    async fn check_source(&self) -> ImageSourceCheck {
+        let this = self.clone();
+        task::spawn_blocking(move || this.scan()).await
+    }
+
+    async fn load_image(&self, index: usize, target: DisplayTarget) -> anyhow::Result<Image> {
+        let this = self.clone();
+        let buffer = task::spawn_blocking(move || this.decode_image(index, &target)).await?;
+        Ok(Image::from_rgba8(buffer))
+    }
+
+    async fn load_thumbnail(&self, index: usize) -> anyhow::Result<Image> {
+        let this = self.clone();
+        let buffer =
+            task::spawn_blocking(move || ThumbnailCache::get_or_create(&this, index)).await?;
+        Ok(Image::from_rgba8(buffer))
+    }
+
+    async fn create_thumbnails(&self, image_count: usize, cancelled: Arc<AtomicBool>) {
+        let this = self.clone();
+        task::spawn_blocking(move || ThumbnailCache::create_first(&this, image_count, &cancelled))
+            .await
+    }
+}
+
+impl<'a> From<&'a ImageSourceArchive> for sg::EditSourceArchiveData {
+    fn from(value: &'a ImageSourceArchive) -> Self {
+        Self {
+            id: value.id.to_string().into(),
+            name: value.name.clone().into(),
+            image_count: 0,
+            path: value.path.to_string_lossy().to_string().into(),
+            weight: value.weight as i32,
+            status: sg::StatusIconData {
+                r#type: sg::StatusIconType::Unknown,
+                error: SharedString::default(),
+            },
+        }
+    }
+}
+
+impl From<ImageSourceArchive> for sg::EditSourceArchiveData {
+    fn from(value: ImageSourceArchive) -> Self {
+        (&value).into()
+    }
+}
+
+impl TryFrom<ImageSource> for sg::EditSourceArchiveData {
+    type Error = anyhow::Error;
+    fn try_from(value: ImageSource) -> Result<Self, Self::Error> {
+        match value {
+            ImageSource::Archive(archive) => Ok(archive.into()),
+            _ => Err(anyhow::anyhow!("Image source is not an archive")),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub use app::AppSourceArchive;
+pub use format::ArchiveFormat;
+pub use image_source::ImageSourceArchive;
+
+mod app;
+mod format;
+mod image_source;
@@ -6,20 +6,55 @@ use uuid::Uuid;
 
 use crate::app::backend::{AppBackendModifications, ImageSourceModification, AppPersistentState};
 use crate::app::image_source::{ImageSource, ImageSourceCheck, ImageSourceTrait};
-use crate::app::image_source::folder::ImageSourceFolder;
+use crate::app::image_source::archive::ImageSourceArchive;
+use crate::app::image_source::folder::{FolderFilter, ImageSourceFolder};
+use crate::app::image_source::list::ImageSourceList;
+use crate::app::image_source::web::ImageSourceWeb;
 use crate::sg;
 
 pub struct ImageSourceBackend {
     image_sources: HashMap<Uuid, ImageSource>,
+    /// Sources checked in the background, their saved check may be outdated,
+    /// and whether they must be checked again once the running check ends
+    running_checks: HashMap<Uuid, bool>,
 }
 
 impl ImageSourceBackend {
     pub fn new() -> Self {
         Self {
             image_sources: HashMap::new(),
+            running_checks: HashMap::new(),
         }
     }
 
+    /// Mark the source as being checked, returns `false` when a check is already running
+    ///
+    /// The running check is then followed by another one, see [`ImageSourceBackend::end_check`].
+    /// Two checks of a source never run at once, the last one to end would replace
+    /// the result of the other.
+    pub fn start_check(&mut self, id: Uuid) -> bool {
+        match self.running_checks.get_mut(&id) {
+            Some(check_again) => {
+                *check_again = true;
+                false
+            }
+            None => {
+                self.running_checks.insert(id, false);
+                true
+            }
+        }
+    }
+
+    /// Returns `true` when another check was requested while this one was running
+    pub fn end_check(&mut self, id: Uuid) -> bool {
+        self.running_checks.remove(&id).unwrap_or_default()
+    }
+
+    /// The source is being checked again, its images may have changed since the saved check
+    pub fn is_checking(&self, id: Uuid) -> bool {
+        self.running_checks.contains_key(&id)
+    }
+
     pub fn update_from_state(&mut self, state: &AppPersistentState) -> anyhow::Result<AppBackendModifications> {
         let deletes = self.image_sources.keys().cloned().map(ImageSourceModification::Deleted).collect::<Vec<_>>();
         
@@ -46,7 +81,7 @@ impl ImageSourceBackend {
         self.image_sources.remove(&uuid)
     }
 
-    pub fn image_sources<'a>(&'a self) -> impl IntoIterator<Item = &'a ImageSource> {
+    pub fn image_sources(&self) -> impl IntoIterator<Item = &ImageSource> {
         self.image_sources.values()
     }
 
@@ -64,21 +99,126 @@ impl ImageSourceBackend {
             // Update image source
             Some(ImageSource::Folder(folder)) => {
                 folder.name = data.name.to_string();
+                folder.weight = data.weight.max(0) as u32;
+                folder.set_filter(FolderFilter::from(&data));
                 if let Some(path) = path {
                     folder.path = path;
                 }
                 ImageSourceModification::Modified(id).into()
             }
             None => {
-                let image_source = ImageSource::Folder(ImageSourceFolder::new(
+                let mut folder = ImageSourceFolder::new(
+                    id,
+                    data.name.to_string(),
+                    path.unwrap_or_else(|| data.path.to_string().into()),
+                    ImageSourceCheck::default(),
+                );
+                folder.weight = data.weight.max(0) as u32;
+                folder.set_filter(FolderFilter::from(&data));
+                let image_source = ImageSource::Folder(folder);
+                self.add_image_source(image_source.clone());
+                ImageSourceModification::Added(image_source.id()).into()
+            }
+            Some(_) => return Err(anyhow::anyhow!("Image source {} is not a folder", id)),
+        })
+    }
+
+    pub fn add_or_update_image_source_from_edit_archive(
+        &mut self,
+        data: &sg::EditSourceArchiveData,
+        path: Option<PathBuf>,
+    ) -> Result<AppBackendModifications, anyhow::Error> {
+        let id = Uuid::from_str(&data.id).unwrap_or_else(|_| Uuid::new_v4());
+
+        // Update backend
+        Ok(match self.get_image_source_mut(id) {
+            // Update image source
+            Some(ImageSource::Archive(archive)) => {
+                archive.name = data.name.to_string();
+                archive.weight = data.weight.max(0) as u32;
+                if let Some(path) = path {
+                    archive.path = path;
+                }
+                ImageSourceModification::Modified(id).into()
+            }
+            None => {
+                let mut archive = ImageSourceArchive::new(
                     id,
                     data.name.to_string(),
                     path.unwrap_or_else(|| data.path.to_string().into()),
                     ImageSourceCheck::default(),
-                ));
+                );
+                archive.weight = data.weight.max(0) as u32;
+                let image_source = ImageSource::Archive(archive);
+                self.add_image_source(image_source.clone());
+                ImageSourceModification::Added(image_source.id()).into()
+            }
+            Some(_) => return Err(anyhow::anyhow!("Image source {} is not an archive", id)),
+        })
+    }
+
+    pub fn add_or_update_image_source_from_edit_list(
+        &mut self,
+        data: &sg::EditSourceListData,
+        path: Option<PathBuf>,
+    ) -> Result<AppBackendModifications, anyhow::Error> {
+        let id = Uuid::from_str(&data.id).unwrap_or_else(|_| Uuid::new_v4());
+
+        // Update backend
+        Ok(match self.get_image_source_mut(id) {
+            // Update image source
+            Some(ImageSource::List(list)) => {
+                list.name = data.name.to_string();
+                list.weight = data.weight.max(0) as u32;
+                if let Some(path) = path {
+                    list.path = path;
+                }
+                ImageSourceModification::Modified(id).into()
+            }
+            None => {
+                let mut list = ImageSourceList::new(
+                    id,
+                    data.name.to_string(),
+                    path.unwrap_or_else(|| data.path.to_string().into()),
+                    ImageSourceCheck::default(),
+                );
+                list.weight = data.weight.max(0) as u32;
+                let image_source = ImageSource::List(list);
+                self.add_image_source(image_source.clone());
+                ImageSourceModification::Added(image_source.id()).into()
+            }
+            Some(_) => return Err(anyhow::anyhow!("Image source {} is not an image list", id)),
+        })
+    }
+
+    pub fn add_or_update_image_source_from_edit_web(
+        &mut self,
+        data: &sg::EditSourceWebData,
+    ) -> Result<AppBackendModifications, anyhow::Error> {
+        let id = Uuid::from_str(&data.id).unwrap_or_else(|_| Uuid::new_v4());
+
+        // Update backend
+        Ok(match self.get_image_source_mut(id) {
+            // Update image source
+            Some(ImageSource::Web(web)) => {
+                web.name = data.name.to_string();
+                web.weight = data.weight.max(0) as u32;
+                web.url = data.url.to_string();
+                ImageSourceModification::Modified(id).into()
+            }
+            None => {
+                let mut web = ImageSourceWeb::new(
+                    id,
+                    data.name.to_string(),
+                    data.url.to_string(),
+                    ImageSourceCheck::default(),
+                );
+                web.weight = data.weight.max(0) as u32;
+                let image_source = ImageSource::Web(web);
                 self.add_image_source(image_source.clone());
                 ImageSourceModification::Added(image_source.id()).into()
             }
+            Some(_) => return Err(anyhow::anyhow!("Image source {} is not a web source", id)),
         })
     }
 }
\ No newline at end of file
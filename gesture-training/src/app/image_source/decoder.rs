@@ -0,0 +1,366 @@
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use image::imageops::FilterType;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader, RgbaImage};
+use qcms::{DataType, Intent, Profile, Transform};
+use serde::{Deserialize, Serialize};
+use slint::{Rgba8Pixel, SharedPixelBuffer};
+
+use crate::app::image_source::file_cache::CachedValue;
+use crate::sg;
+
+/// Extensions of the image files, compared case-insensitively
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "jpe", "jfif", "png", "bmp", "webp", "gif", "tif", "tiff", "avif",
+];
+
+/// Bytes read at the start of a file to recognize its format,
+/// the longest signatures (webp, avif) fit in 12 bytes, the rest is left to `image::guess_format`
+pub const HEADER_LENGTH: usize = 32;
+
+/// How a file found in an image source is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FileKind {
+    /// The content is an image in a format supported by this build
+    Image,
+    /// The name looks like an image but its content can't be decoded
+    Undecodable,
+    /// An image in a format not compiled in this build, like avif without the `avif` feature
+    Unsupported,
+    /// Not an image, silently skipped
+    Other,
+}
+
+/// Kinds of the files of a folder, only the modified files are classified again
+impl CachedValue for FileKind {
+    const DIRECTORY: &'static str = "kinds";
+}
+
+/// Resampling of the images larger than the window, picked in the prepare session page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ImageQuality {
+    Fast,
+    #[default]
+    Balanced,
+    Best,
+    /// Images are decoded at their full resolution
+    Original,
+}
+
+impl ImageQuality {
+    fn filter(self) -> Option<FilterType> {
+        match self {
+            ImageQuality::Fast => Some(FilterType::Triangle),
+            ImageQuality::Balanced => Some(FilterType::CatmullRom),
+            ImageQuality::Best => Some(FilterType::Lanczos3),
+            ImageQuality::Original => None,
+        }
+    }
+}
+
+impl From<sg::ImageQuality> for ImageQuality {
+    fn from(value: sg::ImageQuality) -> Self {
+        match value {
+            sg::ImageQuality::Fast => Self::Fast,
+            sg::ImageQuality::Balanced => Self::Balanced,
+            sg::ImageQuality::Best => Self::Best,
+            sg::ImageQuality::Original => Self::Original,
+        }
+    }
+}
+
+impl From<ImageQuality> for sg::ImageQuality {
+    fn from(value: ImageQuality) -> Self {
+        match value {
+            ImageQuality::Fast => Self::Fast,
+            ImageQuality::Balanced => Self::Balanced,
+            ImageQuality::Best => Self::Best,
+            ImageQuality::Original => Self::Original,
+        }
+    }
+}
+
+/// Physical size of the window displaying the images
+///
+/// Larger images are downscaled to fit in it when decoded, a full resolution
+/// 8K image would use hundreds of MB once uploaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayTarget {
+    pub width: u32,
+    pub height: u32,
+    pub quality: ImageQuality,
+}
+
+impl Default for DisplayTarget {
+    /// Keep images at their full resolution
+    fn default() -> Self {
+        Self {
+            width: u32::MAX,
+            height: u32::MAX,
+            quality: ImageQuality::Original,
+        }
+    }
+}
+
+impl DisplayTarget {
+    /// Downscale an image that doesn't fit, keeping its aspect ratio
+    fn fit(&self, image: DynamicImage, orientation: Orientation) -> DynamicImage {
+        // The orientation is applied after, on the smaller image
+        let (width, height) = match orientation {
+            Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH => (self.height, self.width),
+            _ => (self.width, self.height),
+        };
+
+        match self.quality.filter() {
+            Some(filter) if image.width() > width || image.height() > height => {
+                image.resize(width.max(1), height.max(1), filter)
+            }
+            _ => image,
+        }
+    }
+}
+
+/// Files of a source that are not used as images, reported by its check
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkippedFiles {
+    pub other: usize,
+    pub undecodable: usize,
+    /// Images in a format not compiled in this build
+    pub unsupported: usize,
+    /// Files listed by an image list that don't exist
+    pub missing: usize,
+}
+
+impl SkippedFiles {
+    pub fn count(&mut self, kind: FileKind) {
+        match kind {
+            FileKind::Image => {}
+            FileKind::Undecodable => self.undecodable += 1,
+            FileKind::Unsupported => self.unsupported += 1,
+            FileKind::Other => self.other += 1,
+        }
+    }
+}
+
+/// Check if a path looks like an image file from its extension
+pub fn has_image_extension(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            IMAGE_EXTENSIONS
+                .iter()
+                .any(|image_extension| extension.eq_ignore_ascii_case(image_extension))
+        })
+}
+
+/// Format of an image from the first bytes of its content, if this build can decode it
+///
+/// Formats like avif are only decoded when the matching feature is enabled
+pub fn sniff_image_format(header: &[u8]) -> Option<ImageFormat> {
+    image::guess_format(header)
+        .ok()
+        .filter(|format| format.reading_enabled())
+}
+
+/// Classify a file from its name and the first [`HEADER_LENGTH`] bytes of its content
+///
+/// The content decides, so extension-less or misnamed images are found.
+/// Images in a format of [`IMAGE_EXTENSIONS`] that this build can't decode are reported apart,
+/// the user must know that another build would show them.
+pub fn classify_file(name: impl AsRef<Path>, header: &[u8]) -> FileKind {
+    if sniff_image_format(header).is_some() {
+        FileKind::Image
+    } else if image::guess_format(header).is_ok_and(|format| {
+        format
+            .extensions_str()
+            .iter()
+            .any(|extension| IMAGE_EXTENSIONS.contains(extension))
+    }) {
+        FileKind::Unsupported
+    } else if has_image_extension(name) {
+        FileKind::Undecodable
+    } else {
+        FileKind::Other
+    }
+}
+
+/// Read the first [`HEADER_LENGTH`] bytes of a stream, or less if it is shorter
+pub fn read_header(reader: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(HEADER_LENGTH);
+    reader.take(HEADER_LENGTH as u64).read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Open a file to classify it, see [`classify_file`]
+///
+/// This is a blocking call, don't use it on the event loop
+pub fn classify_path(path: &Path) -> std::io::Result<FileKind> {
+    let header = read_header(File::open(path)?)?;
+    Ok(classify_file(path, &header))
+}
+
+/// Decoded pixels of an image
+///
+/// Unlike `slint::Image`, the buffer can be sent between threads: decode it on a worker
+/// and convert it with `slint::Image::from_rgba8` once back on the event loop.
+pub type DecodedImage = SharedPixelBuffer<Rgba8Pixel>;
+
+/// Decode an encoded image (jpg, png, webp, ...) that is already loaded in memory
+///
+/// The format is found from the content, whatever the name of the file.
+/// The image is downscaled to `target`, rotated by its EXIF orientation and converted
+/// from its embedded color profile to sRGB, like an image viewer shows it.
+/// This is a blocking call, don't use it on the event loop
+pub fn decode_image_from_memory(
+    bytes: &[u8],
+    target: &DisplayTarget,
+) -> anyhow::Result<DecodedImage> {
+    let format = sniff_image_format(&bytes[..bytes.len().min(HEADER_LENGTH)])
+        .ok_or(anyhow::anyhow!("Unsupported image format"))?;
+
+    let mut decoder = ImageReader::with_format(Cursor::new(bytes), format).into_decoder()?;
+    // Broken metadata must not prevent showing the image
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let icc_profile = decoder.icc_profile().ok().flatten();
+
+    let mut image = target.fit(DynamicImage::from_decoder(decoder)?, orientation);
+    image.apply_orientation(orientation);
+    let mut image = image.into_rgba8();
+    if let Some(icc_profile) = icc_profile {
+        convert_to_srgb(&icc_profile, &mut image);
+    }
+
+    Ok(SharedPixelBuffer::<Rgba8Pixel>::clone_from_slice(
+        image.as_raw(),
+        image.width(),
+        image.height(),
+    ))
+}
+
+/// Convert the pixels from an embedded ICC profile to sRGB, the color space of the window
+///
+/// Profiles that can't be read or don't apply to RGB pixels (gray, CMYK) are ignored
+fn convert_to_srgb(icc_profile: &[u8], image: &mut RgbaImage) {
+    let Some(profile) = Profile::new_from_slice(icc_profile, false) else {
+        return;
+    };
+    if profile.is_sRGB() {
+        return;
+    }
+
+    let srgb = Profile::new_sRGB();
+    if let Some(transform) = Transform::new(&profile, &srgb, DataType::RGBA8, Intent::default()) {
+        transform.apply(image);
+    }
+}
+
+/// Read and decode an image file
+///
+/// This is a blocking call, don't use it on the event loop
+pub fn decode_image_from_path(path: &Path, target: &DisplayTarget) -> anyhow::Result<DecodedImage> {
+    let bytes = std::fs::read(path)?;
+    decode_image_from_memory(&bytes, target)
+        .map_err(|error| anyhow::anyhow!("Failed to decode {}: {}", path.display(), error))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::{ExtendedColorType, ImageEncoder};
+
+    use super::*;
+
+    /// EXIF block rotating the image by 90° clockwise
+    const EXIF_ROTATE_90: &[u8] = &[
+        b'M', b'M', 0, 42, 0, 0, 0, 8, // Big endian TIFF header
+        0, 1, // One entry
+        0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, 6, 0, 0, // Orientation, short, 6
+        0, 0, 0, 0, // No next entries
+    ];
+
+    fn jpeg(width: u32, height: u32, exif: &[u8]) -> Vec<u8> {
+        let mut content = Vec::new();
+        let mut encoder = JpegEncoder::new(&mut content);
+        encoder.set_exif_metadata(exif.to_vec()).unwrap();
+        encoder
+            .write_image(
+                &vec![128; (width * height * 3) as usize],
+                width,
+                height,
+                ExtendedColorType::Rgb8,
+            )
+            .unwrap();
+        content
+    }
+
+    fn header(content: &[u8]) -> &[u8] {
+        &content[..content.len().min(HEADER_LENGTH)]
+    }
+
+    #[test]
+    fn files_are_classified_from_their_content() {
+        let jpeg = jpeg(8, 8, &[]);
+        let truncated_jpeg = &jpeg[..jpeg.len() / 2];
+        let avif = b"\0\0\0\x1cftypavif\0\0\0\0avifmif1miaf";
+        let text = b"Not an image";
+
+        let mut skipped_files = SkippedFiles::default();
+        for (name, content) in [
+            ("renamed.txt", &jpeg[..]),
+            ("truncated.jpg", truncated_jpeg),
+            ("text.jpg", text),
+            ("text.txt", text),
+            ("image.avif", avif),
+        ] {
+            skipped_files.count(classify_file(name, header(content)));
+        }
+
+        assert_eq!(skipped_files.undecodable, 1);
+        assert_eq!(skipped_files.other, 1);
+        #[cfg(not(feature = "avif"))]
+        assert_eq!(skipped_files.unsupported, 1);
+        #[cfg(feature = "avif")]
+        assert_eq!(skipped_files.unsupported, 0);
+    }
+
+    #[test]
+    fn truncated_images_fail_to_decode() {
+        let jpeg = jpeg(8, 8, &[]);
+        assert!(decode_image_from_memory(&jpeg, &DisplayTarget::default()).is_ok());
+        let truncated_jpeg = &jpeg[..jpeg.len() / 2];
+        // The header is intact, the file is only found broken when it is shown
+        assert_eq!(classify_file("truncated.jpg", header(truncated_jpeg)), FileKind::Image);
+        assert!(decode_image_from_memory(truncated_jpeg, &DisplayTarget::default()).is_err());
+        assert!(decode_image_from_memory(b"Not an image", &DisplayTarget::default()).is_err());
+    }
+
+    #[test]
+    fn rotated_images_fit_the_target() {
+        let jpeg = jpeg(40, 20, EXIF_ROTATE_90);
+
+        let image = decode_image_from_memory(&jpeg, &DisplayTarget::default()).unwrap();
+        assert_eq!((image.width(), image.height()), (20, 40));
+
+        let target = DisplayTarget {
+            width: 10,
+            height: 40,
+            quality: ImageQuality::Balanced,
+        };
+        let image = decode_image_from_memory(&jpeg, &target).unwrap();
+        assert_eq!((image.width(), image.height()), (10, 20));
+
+        let target = DisplayTarget {
+            quality: ImageQuality::Original,
+            ..target
+        };
+        let image = decode_image_from_memory(&jpeg, &target).unwrap();
+        assert_eq!((image.width(), image.height()), (20, 40));
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use xxhash_rust::xxh3::Xxh3;
+
+use crate::app::image_source::file_cache::{CachedValue, FileCache};
+use crate::app::image_source::thumbnail::ThumbnailKey;
+use crate::app::image_source::ImageSourceCheck;
+use crate::app::log::Log;
+
+/// Hash and size of the bytes of an image file, byte-identical copies have the same one
+///
+/// Both must match for two images to be copies. The 128 bit XXH3 hash keeps collisions,
+/// which would hide a distinct image from the sessions, out of reach of the largest libraries.
+/// Saved by [`HashCache`], XXH3 is stable between builds and platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ContentHash {
+    xxh3: u128,
+    size: u64,
+}
+
+impl CachedValue for ContentHash {
+    const DIRECTORY: &'static str = "hashes";
+}
+
+/// Hashes of the images of a source computed by its previous checks
+pub type HashCache = FileCache<ContentHash>;
+
+/// Hash a whole stream, see [`ContentHash`]
+///
+/// This is a blocking call, don't use it on the event loop
+pub fn hash_content(mut reader: impl Read) -> std::io::Result<ContentHash> {
+    let mut hasher = Xxh3::new();
+    let mut size = 0;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer)? {
+            0 => break,
+            length => {
+                hasher.update(&buffer[..length]);
+                size += length as u64;
+            }
+        }
+    }
+    Ok(ContentHash {
+        xxh3: hasher.digest128(),
+        size,
+    })
+}
+
+/// Hash image files, see [`ContentHash`]
+///
+/// Only the files missing from `cache` are read. Files that can't be read have no hash.
+/// This is a blocking call, don't use it on the event loop
+pub fn hash_files(paths: &[PathBuf], cache: &mut HashCache) -> Vec<Option<ContentHash>> {
+    paths
+        .iter()
+        .map(|path| {
+            ThumbnailKey::from_path(path)
+                .and_then(|key| match cache.get(&key) {
+                    Some(hash) => Ok(hash),
+                    None => {
+                        let hash = File::open(path).and_then(hash_content)?;
+                        cache.insert(&key, hash);
+                        Ok(hash)
+                    }
+                })
+                .map_err(|error| {
+                    Log::handle_error(format!("Failed to read {}: {}", path.display(), error))
+                })
+                .ok()
+        })
+        .collect()
+}
+
+/// Images that are copies of an image found before them, in the order of the checks
+///
+/// Returns the position of the source in `checks` and the index of the image in it.
+/// Images without a hash, like the ones of a source that was not checked yet, are never duplicates.
+pub fn find_duplicate_images<'a>(
+    checks: impl IntoIterator<Item = &'a ImageSourceCheck>,
+) -> HashSet<(usize, usize)> {
+    let mut hashes = HashSet::new();
+    checks
+        .into_iter()
+        .enumerate()
+        .flat_map(|(source_index, check)| {
+            check
+                .content_hashes()
+                .iter()
+                .enumerate()
+                .filter_map(move |(image_index, hash)| {
+                    hash.map(|hash| (source_index, image_index, hash))
+                })
+        })
+        .filter(|(_, _, hash)| !hashes.insert(*hash))
+        .map(|(source_index, image_index, _)| (source_index, image_index))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn copies_have_the_same_hash() {
+        let hash = hash_content(&b"image"[..]).unwrap();
+        assert_eq!(hash_content(&b"image"[..]).unwrap(), hash);
+        assert_ne!(hash_content(&b"imagf"[..]).unwrap(), hash);
+        assert_ne!(hash_content(&b"image "[..]).unwrap(), hash);
+    }
+
+    #[test]
+    fn hashes_are_saved_without_loss() {
+        let hashes = HashMap::from([(1_u64, hash_content(&b"image"[..]).unwrap())]);
+        let content = serde_json::to_vec(&hashes).unwrap();
+        let read = serde_json::from_slice::<HashMap<u64, ContentHash>>(&content).unwrap();
+        assert_eq!(read, hashes);
+    }
+}
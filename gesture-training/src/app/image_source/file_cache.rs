@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::app::atomic_file;
+use crate::app::image_source::cache_directory;
+use crate::app::image_source::thumbnail::ThumbnailKey;
+use crate::app::log::Log;
+use crate::app::stable_hash::stable_hash;
+
+/// Value computed from the content of a file, kept by a [`FileCache`]
+pub trait CachedValue: Copy + PartialEq + Serialize + DeserializeOwned {
+    /// Folder of the cache directory where the values of each source are saved
+    const DIRECTORY: &'static str;
+}
+
+/// Values computed from the files of a source by its previous checks
+///
+/// Saved in the cache directory and keyed by [`ThumbnailKey`], only the new
+/// or modified files are read again by the next check
+pub struct FileCache<V> {
+    /// `None` when the cache is never saved
+    path: Option<PathBuf>,
+    previous: HashMap<u64, V>,
+    /// Values of the files found by this check, the removed files are not saved again
+    current: HashMap<u64, V>,
+}
+
+impl<V: CachedValue> FileCache<V> {
+    /// Read the values of the source at `source_path`, empty if they were never saved
+    ///
+    /// This is a blocking call, don't use it on the event loop
+    pub fn load(source_path: &Path) -> Self {
+        let path = cache_directory().join(V::DIRECTORY).join(format!(
+            "{:016x}.json",
+            stable_hash(source_path.to_string_lossy().as_bytes())
+        ));
+        let previous = match std::fs::read(&path) {
+            // Like a cache saved by an older version, the files are read again
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|error| {
+                Log::handle_error(format!("Failed to read {}: {}", path.display(), error));
+                HashMap::new()
+            }),
+            Err(_) => HashMap::new(),
+        };
+
+        Self {
+            path: Some(path),
+            previous,
+            current: HashMap::new(),
+        }
+    }
+
+    /// Empty cache that is not saved
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        Self {
+            path: None,
+            previous: HashMap::new(),
+            current: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, key: &ThumbnailKey) -> Option<V> {
+        let key = key.stable_hash();
+        let value = self
+            .current
+            .get(&key)
+            .or_else(|| self.previous.get(&key))
+            .copied()?;
+        self.current.insert(key, value);
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: &ThumbnailKey, value: V) {
+        self.current.insert(key.stable_hash(), value);
+    }
+
+    /// Save the values found since [`FileCache::load`]
+    ///
+    /// This is a blocking call, don't use it on the event loop
+    pub fn save(self) {
+        let Some(path) = self.path else {
+            return;
+        };
+        if self.current == self.previous {
+            return;
+        }
+
+        let result = serde_json::to_vec(&self.current)
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(atomic_file::write_atomically(&path, &content)?));
+        if let Err(error) = result {
+            Log::handle_error(format!("Failed to write {}: {}", path.display(), error));
+        }
+    }
+}
@@ -3,6 +3,7 @@ use std::sync::atomic::{AtomicIsize, Ordering};
 
 pub struct AppSourceFolder {
     request_ask_path_id: AtomicIsize,
+    request_subfolders_id: AtomicIsize,
     currently_edited_path: Option<PathBuf>,
 }
 
@@ -10,6 +11,7 @@ impl AppSourceFolder {
     pub fn new() -> Self {
         Self {
             request_ask_path_id: AtomicIsize::new(0),
+            request_subfolders_id: AtomicIsize::new(0),
             currently_edited_path: None,
         }
     }
@@ -18,6 +20,10 @@ impl AppSourceFolder {
         self.request_ask_path_id.fetch_add(1, Ordering::AcqRel)
     }
 
+    pub fn next_request_subfolders_id(&self) -> isize {
+        self.request_subfolders_id.fetch_add(1, Ordering::AcqRel)
+    }
+
     pub fn set_edited_path(&mut self, path: impl Into<PathBuf>) {
         self.currently_edited_path = Some(path.into());
     }
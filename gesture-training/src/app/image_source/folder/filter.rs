@@ -0,0 +1,193 @@
+use std::path::Path;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use slint::Model;
+
+use crate::sg;
+
+/// Separator of the patterns in the folder editor
+const PATTERN_SEPARATOR: char = ';';
+
+/// Selection of the images of a folder
+///
+/// Patterns are matched on the path relative to the folder, `*` also matches `/`.
+/// An image is used when it matches an include pattern (or there is none)
+/// and no exclude pattern.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FolderFilter {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+    /// Depth of the subfolders that are walked, `Some(0)` only uses the images of the folder itself
+    #[serde(default)]
+    max_depth: Option<u32>,
+    /// Names of the immediate subfolders that are skipped, new subfolders are used by default
+    #[serde(default)]
+    excluded_subfolders: Vec<String>,
+}
+
+/// Compiled [`FolderFilter`]
+pub struct FolderFilterMatcher {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl FolderFilter {
+    /// Patterns are separated by `;` as typed in the folder editor
+    pub fn from_text(include: &str, exclude: &str) -> Self {
+        Self {
+            include: Self::split_patterns(include),
+            exclude: Self::split_patterns(exclude),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: Option<u32>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_excluded_subfolders(mut self, excluded_subfolders: Vec<String>) -> Self {
+        self.excluded_subfolders = excluded_subfolders;
+        self.excluded_subfolders.sort();
+        self.excluded_subfolders.dedup();
+        self
+    }
+
+    pub fn max_depth(&self) -> Option<u32> {
+        self.max_depth
+    }
+
+    pub fn excluded_subfolders(&self) -> &[String] {
+        &self.excluded_subfolders
+    }
+
+    /// `depth` is 1 for the immediate subfolders
+    pub fn is_depth_walked(&self, depth: u32) -> bool {
+        self.max_depth.is_none_or(|max_depth| depth <= max_depth)
+    }
+
+    /// `name` is the name of an immediate subfolder
+    pub fn is_subfolder_used(&self, name: &str) -> bool {
+        !self.excluded_subfolders.iter().any(|excluded| excluded == name)
+    }
+
+    fn split_patterns(text: &str) -> Vec<String> {
+        text.split(PATTERN_SEPARATOR)
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn join_patterns(patterns: &[String]) -> String {
+        patterns.join(&format!("{} ", PATTERN_SEPARATOR))
+    }
+
+    pub fn include_text(&self) -> String {
+        Self::join_patterns(&self.include)
+    }
+
+    pub fn exclude_text(&self) -> String {
+        Self::join_patterns(&self.exclude)
+    }
+
+    /// Fails when a pattern is not a valid glob
+    pub fn compile(&self) -> anyhow::Result<FolderFilterMatcher> {
+        fn build(patterns: &[String]) -> anyhow::Result<GlobSet> {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in patterns {
+                builder.add(
+                    Glob::new(pattern)
+                        .map_err(|error| anyhow::anyhow!("Invalid pattern {}: {}", pattern, error))?,
+                );
+            }
+            Ok(builder.build()?)
+        }
+
+        Ok(FolderFilterMatcher {
+            include: if self.include.is_empty() {
+                None
+            } else {
+                Some(build(&self.include)?)
+            },
+            exclude: build(&self.exclude)?,
+        })
+    }
+}
+
+/// Filter edited in the folder editor
+impl<'a> From<&'a sg::EditSourceFolderData> for FolderFilter {
+    fn from(value: &'a sg::EditSourceFolderData) -> Self {
+        let max_depth = match (value.recursive, value.max_depth) {
+            (false, _) => Some(0),
+            (true, max_depth) if max_depth <= 0 => None,
+            (true, max_depth) => Some(max_depth as u32),
+        };
+        let excluded_subfolders = value
+            .subfolders
+            .iter()
+            .filter(|subfolder| !subfolder.used)
+            .map(|subfolder| subfolder.name.to_string())
+            .collect();
+
+        Self::from_text(&value.include, &value.exclude)
+            .with_max_depth(max_depth)
+            .with_excluded_subfolders(excluded_subfolders)
+    }
+}
+
+impl FolderFilterMatcher {
+    /// `relative_path` is relative to the folder of the source
+    pub fn is_match(&self, relative_path: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative_path))
+            && !self.exclude.is_match(relative_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_are_split_on_semicolons() {
+        let filter = FolderFilter::from_text(" *.png;poses/** ; ", ";;");
+        assert_eq!(filter.include_text(), "*.png; poses/**");
+        assert_eq!(filter.exclude_text(), "");
+        assert_eq!(FolderFilter::from_text(&filter.include_text(), ""), filter);
+
+        let matcher = filter.compile().unwrap();
+        assert!(matcher.is_match(Path::new("a.png")));
+        assert!(matcher.is_match(Path::new("sketches/a.png")));
+        assert!(matcher.is_match(Path::new("poses/standing/a.jpg")));
+        assert!(!matcher.is_match(Path::new("a.jpg")));
+    }
+
+    #[test]
+    fn empty_patterns_match_every_file() {
+        let matcher = FolderFilter::from_text("", " ; ").compile().unwrap();
+        assert!(matcher.is_match(Path::new("a.jpg")));
+        assert!(matcher.is_match(Path::new("poses/a.png")));
+    }
+
+    #[test]
+    fn excluded_patterns_win() {
+        let matcher = FolderFilter::from_text("*.png", "drafts/*").compile().unwrap();
+        assert!(matcher.is_match(Path::new("poses/a.png")));
+        assert!(!matcher.is_match(Path::new("drafts/a.png")));
+    }
+
+    #[test]
+    fn invalid_patterns_are_errors() {
+        let error = FolderFilter::from_text("*.png; [a-", "")
+            .compile()
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("[a-"));
+        assert!(FolderFilter::from_text("", "{a,b").compile().is_err());
+    }
+}
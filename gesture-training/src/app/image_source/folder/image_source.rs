@@ -1,21 +1,48 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
-use slint::{Image, SharedString};
+use async_std::task;
+use slint::{Image, ModelRc, SharedString, VecModel};
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
-use crate::app::image_source::{ImageSource, ImageSourceCheck, ImageSourceStatus, ImageSourceTrait};
+use crate::app::image_source::decoder::{self, DecodedImage, FileKind, SkippedFiles};
+use crate::app::image_source::duplicate::{self, HashCache};
+use crate::app::image_source::file_cache::FileCache;
+use crate::app::image_source::folder::FolderFilter;
+use crate::app::image_source::index::{FileStamp, ImageIndex, ImageIndexCache};
+use crate::app::image_source::thumbnail::{ThumbnailCache, ThumbnailKey, ThumbnailSource};
+use crate::app::image_source::{default_weight, DisplayTarget, ImageSource, ImageSourceCheck, ImageSourceStatus, ImageSourceTrait};
 use crate::app::log::Log;
 use crate::sg;
 
 /// Use a folder as an image source
-/// Will look for any file recursively inside the folder
+/// Will look for any file recursively inside the folder, up to the depth of its filter
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageSourceFolder {
     id: Uuid,
     pub(crate) name: String,
     pub(crate) path: PathBuf,
-    #[serde(skip)]
+    /// Relative chance to pick this source with the weighted selection strategy
+    #[serde(default = "default_weight")]
+    pub(crate) weight: u32,
+    /// Images of the folder that are used
+    #[serde(default)]
+    filter: FolderFilter,
+    /// Last check, displayed until the source is checked again
+    #[serde(default)]
     check: ImageSourceCheck,
+    #[serde(skip)]
+    index: ImageIndexCache<PathBuf>,
+}
+
+/// Immediate subfolder of a folder, listed in the folder editor to pick the used ones
+#[derive(Debug, Clone)]
+pub struct FolderSubfolder {
+    pub name: String,
+    /// Images the subfolder brings to the source when it is used
+    pub image_count: usize,
 }
 
 impl ImageSourceFolder {
@@ -24,33 +51,87 @@ impl ImageSourceFolder {
             id,
             name,
             path,
+            weight: default_weight(),
+            filter: FolderFilter::default(),
             check,
+            index: ImageIndexCache::default(),
         }
     }
 
-    const IMAGE_EXTENSIONS: &'static [&'static str] = &["jpg", "jpeg", "png", "bmp"];
-    fn is_image_file(path: impl AsRef<Path>) -> bool {
-        if let Some(extension) = path.as_ref().extension().and_then(|ext| ext.to_str()) {
-            Self::IMAGE_EXTENSIONS.contains(&extension)
-        } else {
-            false
+    pub fn set_filter(&mut self, filter: FolderFilter) {
+        if filter != self.filter {
+            self.filter = filter;
+            // The cached index was built with the previous filter
+            self.index = ImageIndexCache::default();
         }
     }
 
-    async fn find_image_files_in_directory(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
-        let mut paths = vec![path.to_path_buf()];
+    /// Walk the folder to list its images matching the filter, sorted by path
+    ///
+    /// Files are recognized from their content, the other files matching the filter
+    /// are counted as skipped.
+    /// The modification date of each visited directory is stamped on the index,
+    /// so adding or removing a file anywhere in the tree invalidates it.
+    /// Files that were not modified since they were classified keep their kind from `kind_cache`.
+    fn find_image_files_in_directory(
+        path: &Path,
+        filter: &FolderFilter,
+        kind_cache: &mut FileCache<FileKind>,
+    ) -> anyhow::Result<(ImageIndex<PathBuf>, SkippedFiles)> {
+        let matcher = filter.compile()?;
+        // The folder itself is at depth 0
+        let mut paths = vec![(path.to_path_buf(), 0)];
         let mut image_paths = Vec::new();
-        while let Some(current_path) = paths.pop() {
+        let mut skipped_files = SkippedFiles::default();
+        let mut stamps = Vec::new();
+        while let Some((current_path, depth)) = paths.pop() {
+            stamps.push(FileStamp::new(&current_path));
             match std::fs::read_dir(current_path) {
                 Ok(mut read_dir) => loop {
                     match read_dir.next() {
                         Some(Ok(entry)) => {
-                            if Self::is_image_file(&entry.path()) {
-                                image_paths.push(entry.path().into())
-                            } else if let Ok(entry_type) = entry.file_type() {
-                                if entry_type.is_dir() {
-                                    paths.push(entry.path().into())
+                            let entry_path = entry.path();
+                            match entry.file_type() {
+                                Ok(entry_type) if entry_type.is_dir() => {
+                                    if filter.is_depth_walked(depth + 1)
+                                        && (depth > 0
+                                            || filter.is_subfolder_used(
+                                                &entry.file_name().to_string_lossy(),
+                                            ))
+                                    {
+                                        paths.push((entry_path, depth + 1))
+                                    }
+                                }
+                                // Symbolic links to directories are not followed
+                                Ok(_) if entry_path.is_file() => {
+                                    let relative_path =
+                                        entry_path.strip_prefix(path).unwrap_or(&entry_path);
+                                    if !matcher.is_match(relative_path) {
+                                        continue;
+                                    }
+                                    match Self::classify_file(&entry_path, kind_cache) {
+                                        Ok(FileKind::Image) => image_paths.push(entry_path),
+                                        Ok(kind) => {
+                                            if kind == FileKind::Undecodable {
+                                                Log::handle_error(format!(
+                                                    "Can't decode {}",
+                                                    entry_path.display()
+                                                ));
+                                            }
+                                            skipped_files.count(kind)
+                                        }
+                                        Err(error) => {
+                                            Log::handle_error(format!(
+                                                "Failed to read {}: {}",
+                                                entry_path.display(),
+                                                error
+                                            ));
+                                            skipped_files.count(FileKind::Undecodable)
+                                        }
+                                    }
                                 }
+                                Ok(_) => {}
+                                Err(error) => Log::handle_error(&error),
                             }
                         }
                         Some(Err(error)) => {
@@ -69,7 +150,117 @@ impl ImageSourceFolder {
             }
         }
 
-        Ok(image_paths)
+        image_paths.sort();
+        Ok((ImageIndex::new(path, image_paths, stamps), skipped_files))
+    }
+
+    /// Recognize a file from its content, see [`decoder::classify_path`]
+    ///
+    /// Only the first bytes of the new or modified files are read
+    fn classify_file(path: &Path, kind_cache: &mut FileCache<FileKind>) -> std::io::Result<FileKind> {
+        let key = ThumbnailKey::from_path(path)?;
+        if let Some(kind) = kind_cache.get(&key) {
+            return Ok(kind);
+        }
+        let kind = decoder::classify_path(path)?;
+        kind_cache.insert(&key, kind);
+        Ok(kind)
+    }
+
+    /// Walk the folder with the kinds of its files saved by the previous walks
+    fn find_image_files(
+        path: &Path,
+        filter: &FolderFilter,
+    ) -> anyhow::Result<(ImageIndex<PathBuf>, SkippedFiles)> {
+        let mut kind_cache = FileCache::load(path);
+        let result = Self::find_image_files_in_directory(path, filter, &mut kind_cache);
+        kind_cache.save();
+        result
+    }
+
+    /// List the immediate subfolders of `path` sorted by name, with the images they
+    /// bring to a source using `filter` whether they are excluded or not
+    pub async fn list_subfolders(
+        path: PathBuf,
+        filter: FolderFilter,
+    ) -> anyhow::Result<Vec<FolderSubfolder>> {
+        task::spawn_blocking(move || {
+            let mut image_counts = BTreeMap::new();
+            for entry in std::fs::read_dir(&path)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    image_counts.insert(entry.file_name().to_string_lossy().to_string(), 0);
+                }
+            }
+
+            let (index, _) =
+                Self::find_image_files(&path, &filter.with_excluded_subfolders(Vec::new()))?;
+            for image_path in index.entries() {
+                let subfolder = image_path
+                    .strip_prefix(&path)
+                    .ok()
+                    .filter(|relative_path| relative_path.components().count() > 1)
+                    .and_then(|relative_path| relative_path.components().next());
+                if let Some(subfolder) = subfolder {
+                    let name = subfolder.as_os_str().to_string_lossy();
+                    if let Some(image_count) = image_counts.get_mut(name.as_ref()) {
+                        *image_count += 1;
+                    }
+                }
+            }
+
+            Ok(image_counts
+                .into_iter()
+                .map(|(name, image_count)| FolderSubfolder { name, image_count })
+                .collect())
+        })
+        .await
+    }
+
+    /// Blocking part of [`ImageSourceTrait::check_source`]
+    fn scan(&self) -> ImageSourceCheck {
+        Self::find_image_files(&self.path, &self.filter)
+            .map(|(index, skipped_files)| {
+                let index = Arc::new(index);
+                self.index.set(index.clone());
+                let mut hash_cache = HashCache::load(&self.path);
+                let content_hashes = duplicate::hash_files(index.entries(), &mut hash_cache);
+                hash_cache.save();
+                ImageSourceCheck::new(index.len(), ImageSourceStatus::Valid)
+                    .with_skipped_files(skipped_files)
+                    .with_content_hashes(content_hashes)
+            })
+            .unwrap_or_else(|error| {
+                ImageSourceCheck::new(0, ImageSourceStatus::Error(error.to_string()))
+            })
+    }
+
+    fn image_index(&self) -> anyhow::Result<Arc<ImageIndex<PathBuf>>> {
+        self.index.get_or_build(&self.path, || {
+            Self::find_image_files(&self.path, &self.filter).map(|(index, _)| index)
+        })
+    }
+
+    fn image_path(&self, index: usize) -> anyhow::Result<PathBuf> {
+        self.image_index()?
+            .get(index)
+            .cloned()
+            .ok_or(anyhow::anyhow!("No image at index {} in folder", index))
+    }
+}
+
+impl ThumbnailSource for ImageSourceFolder {
+    fn image_count(&self) -> anyhow::Result<usize> {
+        Ok(self.image_index()?.len())
+    }
+
+    fn thumbnail_key(&self, index: usize) -> anyhow::Result<ThumbnailKey> {
+        Ok(ThumbnailKey::from_path(&self.image_path(index)?)?)
+    }
+
+    /// Blocking part of [`ImageSourceTrait::load_image`]
+    fn decode_image(&self, index: usize, target: &DisplayTarget) -> anyhow::Result<DecodedImage> {
+        decoder::decode_image_from_path(&self.image_path(index)?, target)
     }
 }
 
@@ -80,6 +271,9 @@ impl ImageSourceTrait for ImageSourceFolder {
     fn name(&self) -> &str {
         &self.name
     }
+    fn weight(&self) -> u32 {
+        self.weight
+    }
 
     fn check(&self) -> &ImageSourceCheck {
         &self.check
@@ -90,24 +284,45 @@ impl ImageSourceTrait for ImageSourceFolder {
     }
 
     async fn check_source(&self) -> ImageSourceCheck {
-        Self::find_image_files_in_directory(&self.path)
-            .await
-            .map(|paths| ImageSourceCheck {
-                image_count: paths.len(),
-                status: ImageSourceStatus::Valid,
-            })
-            .unwrap_or_else(|error| ImageSourceCheck {
-                image_count: 0,
-                status: ImageSourceStatus::Error(error.to_string()),
-            })
+        let this = self.clone();
+        task::spawn_blocking(move || this.scan()).await
     }
 
-    async fn load_image(&self, index: usize) -> anyhow::Result<Image> {
-        let images = Self::find_image_files_in_directory(&self.path).await?;
-        Ok(slint::Image::load_from_path(&images[index])?)
+    async fn load_image(&self, index: usize, target: DisplayTarget) -> anyhow::Result<Image> {
+        let this = self.clone();
+        let buffer = task::spawn_blocking(move || this.decode_image(index, &target)).await?;
+        Ok(Image::from_rgba8(buffer))
+    }
+
+    async fn load_thumbnail(&self, index: usize) -> anyhow::Result<Image> {
+        let this = self.clone();
+        let buffer =
+            task::spawn_blocking(move || ThumbnailCache::get_or_create(&this, index)).await?;
+        Ok(Image::from_rgba8(buffer))
+    }
+
+    async fn create_thumbnails(&self, image_count: usize, cancelled: Arc<AtomicBool>) {
+        let this = self.clone();
+        task::spawn_blocking(move || ThumbnailCache::create_first(&this, image_count, &cancelled))
+            .await
     }
 }
 
+/// Only the excluded subfolders are known until the editor lists the subfolders
+fn excluded_subfolders_data(filter: &FolderFilter) -> ModelRc<sg::EditSourceFolderSubfolderData> {
+    ModelRc::new(VecModel::from(
+        filter
+            .excluded_subfolders()
+            .iter()
+            .map(|name| sg::EditSourceFolderSubfolderData {
+                name: name.into(),
+                image_count: -1,
+                used: false,
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
 impl<'a> From<&'a ImageSourceFolder> for sg::EditSourceFolderData {
     fn from(value: &'a ImageSourceFolder) -> Self {
         Self {
@@ -115,6 +330,12 @@ impl<'a> From<&'a ImageSourceFolder> for sg::EditSourceFolderData {
             name: value.name.clone().into(),
             image_count: 0,
             path: value.path.to_string_lossy().to_string().into(),
+            weight: value.weight as i32,
+            include: value.filter.include_text().into(),
+            exclude: value.filter.exclude_text().into(),
+            recursive: value.filter.max_depth() != Some(0),
+            max_depth: value.filter.max_depth().unwrap_or(0) as i32,
+            subfolders: excluded_subfolders_data(&value.filter),
             status: sg::StatusIconData {
                 r#type: sg::StatusIconType::Unknown,
                 error: SharedString::default(),
@@ -130,6 +351,12 @@ impl From<ImageSourceFolder> for sg::EditSourceFolderData {
             name: value.name.into(),
             image_count: 0,
             path: value.path.to_string_lossy().to_string().into(),
+            weight: value.weight as i32,
+            include: value.filter.include_text().into(),
+            exclude: value.filter.exclude_text().into(),
+            recursive: value.filter.max_depth() != Some(0),
+            max_depth: value.filter.max_depth().unwrap_or(0) as i32,
+            subfolders: excluded_subfolders_data(&value.filter),
             status: sg::StatusIconData {
                 r#type: sg::StatusIconType::Unknown,
                 error: SharedString::default(),
@@ -141,7 +368,107 @@ impl From<ImageSourceFolder> for sg::EditSourceFolderData {
 impl TryFrom<ImageSource> for sg::EditSourceFolderData {
     type Error = anyhow::Error;
     fn try_from(value: ImageSource) -> Result<Self, Self::Error> {
-        let ImageSource::Folder(folder) = value;
-        Ok(folder.into())
+        match value {
+            ImageSource::Folder(folder) => Ok(folder.into()),
+            _ => Err(anyhow::anyhow!("Image source is not a folder")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io::Cursor;
+    use std::time::{Duration, SystemTime};
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn png() -> Vec<u8> {
+        let mut content = Cursor::new(Vec::new());
+        image::RgbImage::new(2, 2)
+            .write_to(&mut content, image::ImageFormat::Png)
+            .unwrap();
+        content.into_inner()
+    }
+
+    fn set_modified(path: &Path, modified: SystemTime) {
+        File::open(path).unwrap().set_modified(modified).unwrap();
+    }
+
+    fn find_images(path: &Path, kind_cache: &mut FileCache<FileKind>) -> ImageIndex<PathBuf> {
+        ImageSourceFolder::find_image_files_in_directory(path, &FolderFilter::default(), kind_cache)
+            .unwrap()
+            .0
+    }
+
+    #[test]
+    fn index_is_outdated_when_a_file_is_added_or_removed() {
+        let folder = TempDir::new().unwrap();
+        let subfolder = folder.path().join("subfolder");
+        std::fs::create_dir(&subfolder).unwrap();
+        std::fs::write(folder.path().join("a.png"), png()).unwrap();
+        std::fs::write(subfolder.join("b.png"), png()).unwrap();
+        // Far from now, so a coarse modification date still changes with the next file
+        let past = SystemTime::now() - Duration::from_secs(3600);
+        let reset = || {
+            set_modified(folder.path(), past);
+            set_modified(&subfolder, past);
+        };
+        let mut kind_cache = FileCache::in_memory();
+
+        reset();
+        let index = find_images(folder.path(), &mut kind_cache);
+        assert_eq!(index.len(), 2);
+        assert!(index.is_up_to_date());
+
+        std::fs::write(subfolder.join("c.png"), png()).unwrap();
+        assert!(!index.is_up_to_date());
+        reset();
+        let index = find_images(folder.path(), &mut kind_cache);
+        assert_eq!(index.len(), 3);
+        assert!(index.is_up_to_date());
+
+        std::fs::remove_file(folder.path().join("a.png")).unwrap();
+        assert!(!index.is_up_to_date());
+        reset();
+        let index = find_images(folder.path(), &mut kind_cache);
+        assert_eq!(index.entries(), [subfolder.join("b.png"), subfolder.join("c.png")]);
+        assert!(index.is_up_to_date());
+
+        std::fs::remove_dir_all(&subfolder).unwrap();
+        assert!(!index.is_up_to_date());
+    }
+
+    #[test]
+    fn unmodified_files_keep_their_kind() {
+        let folder = TempDir::new().unwrap();
+        let path = folder.path().join("image.png");
+        let content = png();
+        std::fs::write(&path, &content).unwrap();
+        let mut kind_cache = FileCache::in_memory();
+        assert_eq!(find_images(folder.path(), &mut kind_cache).len(), 1);
+
+        // Same size and modification date, the file is not read again
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, vec![0; content.len()]).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(find_images(folder.path(), &mut kind_cache).len(), 1);
+
+        set_modified(&path, modified - Duration::from_secs(3600));
+        let (index, skipped_files) = ImageSourceFolder::find_image_files_in_directory(
+            folder.path(),
+            &FolderFilter::default(),
+            &mut kind_cache,
+        )
+        .unwrap();
+        assert_eq!(index.len(), 0);
+        assert_eq!(skipped_files.undecodable, 1);
     }
 }
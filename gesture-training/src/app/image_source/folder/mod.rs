@@ -1,5 +1,7 @@
 pub use app::AppSourceFolder;
+pub use filter::FolderFilter;
 pub use image_source::ImageSourceFolder;
 
 mod app;
+mod filter;
 mod image_source;
@@ -0,0 +1,165 @@
+use std::fmt::{Debug, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Lookups in a row, like the images of a thumbnail job, only check the stamps
+/// of the index once per interval
+const VALIDATION_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Modification date of a file or directory at the time an index was built
+#[derive(Debug, Clone)]
+pub struct FileStamp {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = Self::modified(&path);
+        Self { path, modified }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    pub fn is_up_to_date(&self) -> bool {
+        self.modified.is_some() && Self::modified(&self.path) == self.modified
+    }
+}
+
+/// Sorted list of the images of a source
+///
+/// The index stays valid as long as none of its stamps changed
+#[derive(Debug)]
+pub struct ImageIndex<T> {
+    source: PathBuf,
+    entries: Vec<T>,
+    stamps: Vec<FileStamp>,
+}
+
+impl<T> ImageIndex<T> {
+    pub fn new(source: impl Into<PathBuf>, entries: Vec<T>, stamps: Vec<FileStamp>) -> Self {
+        Self {
+            source: source.into(),
+            entries,
+            stamps,
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        self.entries.get(index)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn entries(&self) -> &[T] {
+        &self.entries
+    }
+
+    /// Stat every stamp, use [`ImageIndexCache::get`] to do it once for many lookups
+    pub fn is_up_to_date(&self) -> bool {
+        self.stamps.iter().all(FileStamp::is_up_to_date)
+    }
+}
+
+struct CachedIndex<T> {
+    index: Arc<ImageIndex<T>>,
+    /// Last time the stamps of the index were checked
+    validated_at: Instant,
+}
+
+/// Last index built for a source
+///
+/// Shared between all the clones of an image source so an index built
+/// by `check_source` is reused by the session when loading images
+pub struct ImageIndexCache<T> {
+    index: Arc<Mutex<Option<CachedIndex<T>>>>,
+}
+
+impl<T> ImageIndexCache<T> {
+    pub fn set(&self, index: Arc<ImageIndex<T>>) {
+        *self.index.lock().unwrap_or_else(|error| error.into_inner()) = Some(CachedIndex {
+            index,
+            validated_at: Instant::now(),
+        });
+    }
+
+    /// Get the cached index if it was built for `source` and is still up-to-date
+    ///
+    /// The stamps are checked at most once per [`VALIDATION_INTERVAL`], without holding the lock
+    pub fn get(&self, source: &Path) -> Option<Arc<ImageIndex<T>>> {
+        let (index, validated_at) = self
+            .index
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .as_ref()
+            .filter(|cached| cached.index.source == source)
+            .map(|cached| (cached.index.clone(), cached.validated_at))?;
+        if validated_at.elapsed() < VALIDATION_INTERVAL {
+            return Some(index);
+        }
+
+        if !index.is_up_to_date() {
+            return None;
+        }
+        if let Some(cached) = self
+            .index
+            .lock()
+            .unwrap_or_else(|error| error.into_inner())
+            .as_mut()
+            .filter(|cached| Arc::ptr_eq(&cached.index, &index))
+        {
+            cached.validated_at = Instant::now();
+        }
+        Some(index)
+    }
+
+    pub fn get_or_build(
+        &self,
+        source: &Path,
+        build: impl FnOnce() -> anyhow::Result<ImageIndex<T>>,
+    ) -> anyhow::Result<Arc<ImageIndex<T>>> {
+        match self.get(source) {
+            Some(index) => Ok(index),
+            None => {
+                let index = Arc::new(build()?);
+                self.set(index.clone());
+                Ok(index)
+            }
+        }
+    }
+}
+
+impl<T> Default for ImageIndexCache<T> {
+    fn default() -> Self {
+        Self {
+            index: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl<T> Clone for ImageIndexCache<T> {
+    fn clone(&self) -> Self {
+        Self {
+            index: self.index.clone(),
+        }
+    }
+}
+
+impl<T> Debug for ImageIndexCache<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let len = self
+            .index
+            .lock()
+            .ok()
+            .and_then(|cached| cached.as_ref().map(|cached| cached.index.len()));
+        f.debug_struct("ImageIndexCache").field("len", &len).finish()
+    }
+}
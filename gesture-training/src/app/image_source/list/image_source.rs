@@ -0,0 +1,271 @@
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use async_std::task;
+use serde::{Deserialize, Serialize};
+use slint::{Image, SharedString};
+use uuid::Uuid;
+
+use crate::app::image_source::decoder::{self, DecodedImage, FileKind, SkippedFiles};
+use crate::app::image_source::duplicate::{self, HashCache};
+use crate::app::image_source::index::{FileStamp, ImageIndex, ImageIndexCache};
+use crate::app::image_source::thumbnail::{ThumbnailCache, ThumbnailKey, ThumbnailSource};
+use crate::app::image_source::{
+    default_weight, DisplayTarget, ImageSource, ImageSourceCheck, ImageSourceStatus, ImageSourceTrait,
+};
+use crate::app::log::Log;
+use crate::sg;
+
+/// Use a text file listing images as an image source, like a M3U playlist
+///
+/// Each line is the path of an image, relative to the list file unless absolute.
+/// Empty lines and lines starting with `#` are ignored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageSourceList {
+    id: Uuid,
+    pub(crate) name: String,
+    pub(crate) path: PathBuf,
+    /// Relative chance to pick this source with the weighted selection strategy
+    #[serde(default = "default_weight")]
+    pub(crate) weight: u32,
+    /// Last check, displayed until the source is checked again
+    #[serde(default)]
+    check: ImageSourceCheck,
+    #[serde(skip)]
+    index: ImageIndexCache<PathBuf>,
+}
+
+impl ImageSourceList {
+    /// Extensions used by the file picker
+    pub const EXTENSIONS: &'static [&'static str] = &["txt", "lst", "m3u", "m3u8"];
+
+    pub fn new(id: Uuid, name: String, path: PathBuf, check: ImageSourceCheck) -> Self {
+        Self {
+            id,
+            name,
+            path,
+            weight: default_weight(),
+            check,
+            index: ImageIndexCache::default(),
+        }
+    }
+
+    /// Paths listed by the content of a list file, in the order of the list
+    fn parse_list(list_path: &Path, content: &str) -> Vec<PathBuf> {
+        let directory = list_path.parent().unwrap_or(Path::new(""));
+        content
+            .lines()
+            .map(|line| line.trim_start_matches('\u{feff}').trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| directory.join(line))
+            .collect()
+    }
+
+    /// Read the list and check each listed file, keeping the order of the list
+    ///
+    /// Files are recognized from their content, the other ones are counted as skipped.
+    /// The list and the folders of the listed files are stamped on the index.
+    fn build_index(&self) -> anyhow::Result<(ImageIndex<PathBuf>, SkippedFiles)> {
+        let content = std::fs::read_to_string(&self.path)?;
+        let listed_paths = Self::parse_list(&self.path, &content);
+
+        let mut image_paths = Vec::new();
+        let mut skipped_files = SkippedFiles::default();
+        let mut directories = BTreeSet::new();
+        for listed_path in listed_paths {
+            if let Some(directory) = listed_path.parent() {
+                directories.insert(directory.to_path_buf());
+            }
+            if !listed_path.is_file() {
+                Log::handle_error(format!("Listed file not found {}", listed_path.display()));
+                skipped_files.missing += 1;
+                continue;
+            }
+            match decoder::classify_path(&listed_path) {
+                Ok(FileKind::Image) => image_paths.push(listed_path),
+                Ok(kind) => {
+                    if kind == FileKind::Undecodable {
+                        Log::handle_error(format!("Can't decode {}", listed_path.display()));
+                    }
+                    skipped_files.count(kind)
+                }
+                Err(error) => {
+                    Log::handle_error(format!(
+                        "Failed to read {}: {}",
+                        listed_path.display(),
+                        error
+                    ));
+                    skipped_files.count(FileKind::Undecodable)
+                }
+            }
+        }
+
+        let stamps = std::iter::once(FileStamp::new(&self.path))
+            .chain(directories.into_iter().map(FileStamp::new))
+            .collect();
+        Ok((ImageIndex::new(&self.path, image_paths, stamps), skipped_files))
+    }
+
+    /// Blocking part of [`ImageSourceTrait::check_source`]
+    fn scan(&self) -> ImageSourceCheck {
+        self.build_index()
+            .map(|(index, skipped_files)| {
+                let index = Arc::new(index);
+                self.index.set(index.clone());
+                let mut hash_cache = HashCache::load(&self.path);
+                let content_hashes = duplicate::hash_files(index.entries(), &mut hash_cache);
+                hash_cache.save();
+                ImageSourceCheck::new(index.len(), ImageSourceStatus::Valid)
+                    .with_skipped_files(skipped_files)
+                    .with_content_hashes(content_hashes)
+            })
+            .unwrap_or_else(|error| {
+                ImageSourceCheck::new(0, ImageSourceStatus::Error(error.to_string()))
+            })
+    }
+
+    fn image_index(&self) -> anyhow::Result<Arc<ImageIndex<PathBuf>>> {
+        self.index
+            .get_or_build(&self.path, || self.build_index().map(|(index, _)| index))
+    }
+
+    fn image_path(&self, index: usize) -> anyhow::Result<PathBuf> {
+        self.image_index()?
+            .get(index)
+            .cloned()
+            .ok_or(anyhow::anyhow!("No image at index {} in list", index))
+    }
+}
+
+impl ThumbnailSource for ImageSourceList {
+    fn image_count(&self) -> anyhow::Result<usize> {
+        Ok(self.image_index()?.len())
+    }
+
+    fn thumbnail_key(&self, index: usize) -> anyhow::Result<ThumbnailKey> {
+        Ok(ThumbnailKey::from_path(&self.image_path(index)?)?)
+    }
+
+    /// Blocking part of [`ImageSourceTrait::load_image`]
+    fn decode_image(&self, index: usize, target: &DisplayTarget) -> anyhow::Result<DecodedImage> {
+        decoder::decode_image_from_path(&self.image_path(index)?, target)
+    }
+}
+
+impl ImageSourceTrait for ImageSourceList {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    fn check(&self) -> &ImageSourceCheck {
+        &self.check
+    }
+
+    fn set_check(&mut self, check: ImageSourceCheck) {
+        self.check = check;
+    }
+
+    async fn check_source(&self) -> ImageSourceCheck {
+        let this = self.clone();
+        task::spawn_blocking(move || this.scan()).await
+    }
+
+    async fn load_image(&self, index: usize, target: DisplayTarget) -> anyhow::Result<Image> {
+        let this = self.clone();
+        let buffer = task::spawn_blocking(move || this.decode_image(index, &target)).await?;
+        Ok(Image::from_rgba8(buffer))
+    }
+
+    async fn load_thumbnail(&self, index: usize) -> anyhow::Result<Image> {
+        let this = self.clone();
+        let buffer =
+            task::spawn_blocking(move || ThumbnailCache::get_or_create(&this, index)).await?;
+        Ok(Image::from_rgba8(buffer))
+    }
+
+    async fn create_thumbnails(&self, image_count: usize, cancelled: Arc<AtomicBool>) {
+        let this = self.clone();
+        task::spawn_blocking(move || ThumbnailCache::create_first(&this, image_count, &cancelled))
+            .await
+    }
+}
+
+impl<'a> From<&'a ImageSourceList> for sg::EditSourceListData {
+    fn from(value: &'a ImageSourceList) -> Self {
+        Self {
+            id: value.id.to_string().into(),
+            name: value.name.clone().into(),
+            image_count: 0,
+            path: value.path.to_string_lossy().to_string().into(),
+            weight: value.weight as i32,
+            status: sg::StatusIconData {
+                r#type: sg::StatusIconType::Unknown,
+                error: SharedString::default(),
+            },
+        }
+    }
+}
+
+impl From<ImageSourceList> for sg::EditSourceListData {
+    fn from(value: ImageSourceList) -> Self {
+        (&value).into()
+    }
+}
+
+impl TryFrom<ImageSource> for sg::EditSourceListData {
+    type Error = anyhow::Error;
+    fn try_from(value: ImageSource) -> Result<Self, Self::Error> {
+        match value {
+            ImageSource::List(list) => Ok(list.into()),
+            _ => Err(anyhow::anyhow!("Image source is not an image list")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Vec<PathBuf> {
+        ImageSourceList::parse_list(Path::new("/lists/poses.m3u"), content)
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_list() {
+        assert_eq!(
+            parse("a.jpg\nposes/b.png\n../c.png\n/images/d.png"),
+            [
+                PathBuf::from("/lists/a.jpg"),
+                PathBuf::from("/lists/poses/b.png"),
+                PathBuf::from("/lists/../c.png"),
+                PathBuf::from("/images/d.png"),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_ignored() {
+        assert_eq!(
+            parse("#EXTM3U\n\n  \n# a.jpg\nb.jpg\r\n  c.jpg  \n#EXTINF:-1,d.jpg\n"),
+            [PathBuf::from("/lists/b.jpg"), PathBuf::from("/lists/c.jpg")]
+        );
+        assert!(parse("").is_empty());
+    }
+
+    #[test]
+    fn byte_order_mark_is_stripped() {
+        assert_eq!(
+            parse("\u{feff}a.jpg\nb.jpg"),
+            [PathBuf::from("/lists/a.jpg"), PathBuf::from("/lists/b.jpg")]
+        );
+        assert!(parse("\u{feff}#EXTM3U").is_empty());
+    }
+}
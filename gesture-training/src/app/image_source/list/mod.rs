@@ -0,0 +1,5 @@
+pub use app::AppSourceList;
+pub use image_source::ImageSourceList;
+
+mod app;
+mod image_source;
@@ -1,34 +1,130 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
 use slint::{Image, SharedString};
 use uuid::Uuid;
 
+use archive::ImageSourceArchive;
 use folder::ImageSourceFolder;
+use list::ImageSourceList;
+use web::ImageSourceWeb;
 use serde::{Serialize, Deserialize};
 
 pub use backend::ImageSourceBackend;
+pub use decoder::{DisplayTarget, ImageQuality};
+pub use duplicate::find_duplicate_images;
+pub use thumbnail::ThumbnailJobs;
+pub use watcher::{ImageSourceWatcher, WatchedPath};
 
 use crate::sg;
+use decoder::SkippedFiles;
+use duplicate::ContentHash;
 
+pub mod archive;
 pub mod folder;
+pub mod list;
+pub mod web;
 mod backend;
+mod decoder;
+mod duplicate;
+mod file_cache;
+mod index;
+mod thumbnail;
+mod watcher;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Directory of the files the image sources can create again, like thumbnails or downloads
+fn cache_directory() -> PathBuf {
+    #[cfg(any(target_os = "linux", target_os = "windows", target_os = "macos"))]
+    {
+        let mut path = dirs::cache_dir().unwrap_or_else(|| "~/.cache".into());
+        path.push("GestureTraining");
+        path
+    }
+
+    #[cfg(target_os = "android")]
+    {
+        PathBuf::from("/data/data/org.fredericvauchelles.gesture_training/cache")
+    }
+}
+
+/// Remove the files of a cache directory used the longest time ago until they fit in `budget`
+///
+/// Only the files with `extension` are counted, their modification date tells when they
+/// were last used. `remove` also removes the files that go with one, if any.
+fn evict_least_recently_used(
+    directory: &Path,
+    extension: &str,
+    budget: u64,
+    remove: impl Fn(&Path) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut files = std::fs::read_dir(directory)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|value| value == extension))
+        .filter_map(|path| {
+            let metadata = std::fs::metadata(&path).ok()?;
+            Some((metadata.modified().ok()?, metadata.len(), path))
+        })
+        .collect::<Vec<_>>();
+    files.sort_by_key(|(modified, _, _)| *modified);
+
+    let mut total_size = files.iter().map(|(_, size, _)| size).sum::<u64>();
+    for (_, size, path) in files {
+        if total_size <= budget {
+            break;
+        }
+        // A file still read, on Windows, is removed by a later eviction
+        match remove(&path) {
+            Ok(()) => total_size -= size,
+            Err(error) => crate::app::log::Log::handle_error(format!(
+                "Failed to remove {}: {}",
+                path.display(),
+                error
+            )),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ImageSourceStatus {
+    #[default]
     Unknown,
     Valid,
     Error(String),
 }
 
-impl Default for ImageSourceStatus {
-    fn default() -> Self {
-        Self::Unknown
-    }
-}
-
 /// Status of the last validity check on the source
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ImageSourceCheck {
     image_count: usize,
     status: ImageSourceStatus,
+    /// When the source was checked, `None` if it never was
+    #[serde(default)]
+    checked_at: Option<SystemTime>,
+    /// Files that are not images
+    #[serde(default)]
+    skipped_count: usize,
+    /// Files named like images that can't be decoded, like a corrupted file or an unsupported format
+    #[serde(default)]
+    undecodable_count: usize,
+    /// Images in a format not compiled in this build, like avif
+    #[serde(default)]
+    unsupported_count: usize,
+    /// Files listed by an image list that don't exist
+    #[serde(default)]
+    missing_count: usize,
+    /// Problem that doesn't prevent using the source, like a manifest used offline
+    #[serde(default)]
+    warning: Option<String>,
+    /// Hash of each image, `None` when it could not be read
+    ///
+    /// Not saved with the state, each check reads them from the hash cache of the source
+    /// and only hashes the new or modified images
+    #[serde(skip)]
+    content_hashes: Arc<Vec<Option<ContentHash>>>,
 }
 
 impl ImageSourceCheck {
@@ -43,8 +139,91 @@ impl ImageSourceCheck {
         Self {
             image_count,
             status,
+            checked_at: None,
+            skipped_count: 0,
+            undecodable_count: 0,
+            unsupported_count: 0,
+            missing_count: 0,
+            warning: None,
+            content_hashes: Arc::default(),
         }
     }
+
+    pub fn with_content_hashes(mut self, content_hashes: Vec<Option<ContentHash>>) -> Self {
+        self.content_hashes = Arc::new(content_hashes);
+        self
+    }
+
+    /// Hashes of the images in the order of the source, empty when the source was not checked
+    pub fn content_hashes(&self) -> &[Option<ContentHash>] {
+        &self.content_hashes
+    }
+
+    pub fn with_skipped_files(mut self, skipped_files: SkippedFiles) -> Self {
+        self.skipped_count = skipped_files.other;
+        self.undecodable_count = skipped_files.undecodable;
+        self.unsupported_count = skipped_files.unsupported;
+        self.missing_count = skipped_files.missing;
+        self
+    }
+
+    pub fn with_warning(mut self, warning: String) -> Self {
+        self.warning = Some(warning);
+        self
+    }
+
+    pub fn warning(&self) -> Option<&str> {
+        self.warning.as_deref()
+    }
+
+    /// Explain which files of the source are not used, `None` when all of them are
+    pub fn skipped_files_message(&self) -> Option<String> {
+        let messages = [
+            (self.missing_count, "listed images not available"),
+            (self.undecodable_count, "images could not be decoded"),
+            (
+                self.unsupported_count,
+                "images in a format not compiled in this build",
+            ),
+            (self.skipped_count, "other files skipped"),
+        ]
+        .into_iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, message)| format!("{} {}", count, message))
+        .collect::<Vec<_>>();
+
+        (!messages.is_empty()).then(|| messages.join(", "))
+    }
+
+    /// Mark the check as done now
+    pub fn checked_now(mut self) -> Self {
+        self.checked_at = Some(SystemTime::now());
+        self
+    }
+
+    /// Tell how long ago the source was checked, like "checked 3 days ago"
+    pub fn checked_at_message(&self) -> Option<String> {
+        let elapsed = SystemTime::now()
+            .duration_since(self.checked_at?)
+            .unwrap_or_default();
+        Some(format!("checked {}", format_elapsed(elapsed)))
+    }
+
+    /// Compare the results of two checks, whenever they were done
+    ///
+    /// The hashes are not saved, they are only compared when both checks computed them
+    pub fn has_same_result(&self, other: &ImageSourceCheck) -> bool {
+        self.image_count == other.image_count
+            && self.status == other.status
+            && self.skipped_count == other.skipped_count
+            && self.undecodable_count == other.undecodable_count
+            && self.unsupported_count == other.unsupported_count
+            && self.missing_count == other.missing_count
+            && self.warning == other.warning
+            && (self.content_hashes.is_empty()
+                || other.content_hashes.is_empty()
+                || self.content_hashes == other.content_hashes)
+    }
 }
 
 impl From<ImageSourceStatus> for sg::StatusIconData {
@@ -84,65 +263,169 @@ impl<'a> From<&'a ImageSourceStatus> for sg::StatusIconData {
     }
 }
 
+/// Status of a check, with the warning and the skipped files of a valid source
+/// and when it was checked
+impl<'a> From<&'a ImageSourceCheck> for sg::StatusIconData {
+    fn from(value: &'a ImageSourceCheck) -> Self {
+        let mut data = sg::StatusIconData::from(value.status());
+        let is_valid = value.status() == &ImageSourceStatus::Valid;
+        if is_valid && value.warning().is_some() {
+            data.r#type = sg::StatusIconType::Warning;
+        }
+        let messages = [
+            (!data.error.is_empty()).then(|| data.error.to_string()),
+            value.warning().filter(|_| is_valid).map(str::to_string),
+            value.skipped_files_message().filter(|_| is_valid),
+            value.checked_at_message(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        data.error = messages.join("\n").into();
+        data
+    }
+}
+
+/// Round a duration to its largest unit, like "3 days ago"
+fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    let (count, unit) = match seconds {
+        0..60 => return "just now".to_string(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86400 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    format!("{} {}{} ago", count, unit, if count > 1 { "s" } else { "" })
+}
+
+pub(crate) fn default_weight() -> u32 {
+    1
+}
+
 pub trait ImageSourceTrait {
     fn id(&self) -> Uuid;
     fn name(&self) -> &str;
+    fn weight(&self) -> u32;
     fn check(&self) -> &ImageSourceCheck;
     fn set_check(&mut self, check: ImageSourceCheck);
     async fn check_source(&self) -> ImageSourceCheck;
-    async fn load_image(&self, index: usize) -> anyhow::Result<slint::Image>;
+    /// Decode an image, downscaled to fit in `target`
+    async fn load_image(&self, index: usize, target: DisplayTarget) -> anyhow::Result<slint::Image>;
+    /// Thumbnail of an image, read from the thumbnail cache or created
+    async fn load_thumbnail(&self, index: usize) -> anyhow::Result<slint::Image>;
+    /// Create the missing thumbnails of the first `image_count` images found by the last check,
+    /// until `cancelled` is set
+    async fn create_thumbnails(&self, image_count: usize, cancelled: Arc<AtomicBool>);
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum ImageSource {
     Folder(ImageSourceFolder),
+    Archive(ImageSourceArchive),
+    List(ImageSourceList),
+    Web(ImageSourceWeb),
 }
 
 impl ImageSourceTrait for ImageSource {
     fn id(&self) -> Uuid {
         match self {
             ImageSource::Folder(value) => value.id(),
+            ImageSource::Archive(value) => value.id(),
+            ImageSource::List(value) => value.id(),
+            ImageSource::Web(value) => value.id(),
         }
     }
 
     fn name(&self) -> &str {
         match self {
             ImageSource::Folder(value) => value.name(),
+            ImageSource::Archive(value) => value.name(),
+            ImageSource::List(value) => value.name(),
+            ImageSource::Web(value) => value.name(),
+        }
+    }
+
+    fn weight(&self) -> u32 {
+        match self {
+            ImageSource::Folder(value) => value.weight(),
+            ImageSource::Archive(value) => value.weight(),
+            ImageSource::List(value) => value.weight(),
+            ImageSource::Web(value) => value.weight(),
         }
     }
 
     fn check(&self) -> &ImageSourceCheck {
         match self {
             ImageSource::Folder(value) => value.check(),
+            ImageSource::Archive(value) => value.check(),
+            ImageSource::List(value) => value.check(),
+            ImageSource::Web(value) => value.check(),
         }
     }
 
     fn set_check(&mut self, check: ImageSourceCheck) {
         match self {
             ImageSource::Folder(value) => value.set_check(check),
+            ImageSource::Archive(value) => value.set_check(check),
+            ImageSource::List(value) => value.set_check(check),
+            ImageSource::Web(value) => value.set_check(check),
         }
     }
 
     async fn check_source(&self) -> ImageSourceCheck {
         match self {
             ImageSource::Folder(value) => value.check_source().await,
+            ImageSource::Archive(value) => value.check_source().await,
+            ImageSource::List(value) => value.check_source().await,
+            ImageSource::Web(value) => value.check_source().await,
+        }
+    }
+
+    async fn load_image(&self, index: usize, target: DisplayTarget) -> anyhow::Result<Image> {
+        match self {
+            ImageSource::Folder(value) => value.load_image(index, target).await,
+            ImageSource::Archive(value) => value.load_image(index, target).await,
+            ImageSource::List(value) => value.load_image(index, target).await,
+            ImageSource::Web(value) => value.load_image(index, target).await,
+        }
+    }
+
+    async fn load_thumbnail(&self, index: usize) -> anyhow::Result<Image> {
+        match self {
+            ImageSource::Folder(value) => value.load_thumbnail(index).await,
+            ImageSource::Archive(value) => value.load_thumbnail(index).await,
+            ImageSource::List(value) => value.load_thumbnail(index).await,
+            ImageSource::Web(value) => value.load_thumbnail(index).await,
         }
     }
 
-    async fn load_image(&self, index: usize) -> anyhow::Result<Image> {
+    async fn create_thumbnails(&self, image_count: usize, cancelled: Arc<AtomicBool>) {
         match self {
-            ImageSource::Folder(value) => value.load_image(index).await,
+            ImageSource::Folder(value) => value.create_thumbnails(image_count, cancelled).await,
+            ImageSource::Archive(value) => value.create_thumbnails(image_count, cancelled).await,
+            ImageSource::List(value) => value.create_thumbnails(image_count, cancelled).await,
+            ImageSource::Web(value) => value.create_thumbnails(image_count, cancelled).await,
         }
     }
 }
 
 impl ImageSource {
+    /// Remove the files the source created in the cache directory, once it is deleted
+    ///
+    /// Thumbnails are shared with other sources using the same images, they are kept
+    pub fn remove_cached_files(&self) -> anyhow::Result<()> {
+        match self {
+            ImageSource::Archive(archive) => archive.remove_cached_files(),
+            ImageSource::Folder(_) | ImageSource::List(_) | ImageSource::Web(_) => Ok(()),
+        }
+    }
+
     pub(crate) fn update_image_source_selector_entry(
         &self,
         target: &mut sg::ImageSourceSelectorEntryData,
     ) {
         target.name = self.name().to_string().into();
         target.image_count = self.check().image_count() as i32;
-        target.status = self.check().status().into();
+        target.status = self.check().into();
     }
 }
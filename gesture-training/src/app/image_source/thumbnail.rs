@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use image::{ImageFormat, RgbaImage};
+use uuid::Uuid;
+
+use crate::app::atomic_file;
+use crate::app::image_source::decoder::{self, DecodedImage, DisplayTarget, ImageQuality};
+use crate::app::image_source::{cache_directory, evict_least_recently_used};
+use crate::app::log::Log;
+use crate::app::stable_hash::StableHasher;
+
+/// Largest side of a thumbnail, in pixels
+pub const THUMBNAIL_SIZE: u32 = 192;
+
+/// Disk space used by the thumbnails, the thumbnails used the longest time ago are removed beyond it
+const THUMBNAIL_CACHE_BUDGET: u64 = 256 * 1024 * 1024;
+
+const THUMBNAIL_TARGET: DisplayTarget = DisplayTarget {
+    width: THUMBNAIL_SIZE,
+    height: THUMBNAIL_SIZE,
+    quality: ImageQuality::Balanced,
+};
+
+/// Identify the content of an image, its thumbnail is created again when it changes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ThumbnailKey {
+    location: String,
+    modified: Option<SystemTime>,
+    size: u64,
+}
+
+impl ThumbnailKey {
+    /// Key of an image file, or of an archive containing the image
+    pub fn from_path(path: &Path) -> std::io::Result<Self> {
+        let metadata = std::fs::metadata(path)?;
+        Ok(Self {
+            location: path.to_string_lossy().to_string(),
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+        })
+    }
+
+    /// Key of an entry of the archive identified by this key
+    pub fn with_entry(mut self, entry_name: &str) -> Self {
+        self.location.push('#');
+        self.location.push_str(entry_name);
+        self
+    }
+
+    /// Hash of the key, stable between runs
+    pub fn stable_hash(&self) -> u64 {
+        let modified = self
+            .modified
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_nanos())
+            .unwrap_or_default();
+
+        let mut hasher = StableHasher::default();
+        hasher.write(self.location.as_bytes());
+        hasher.write(&modified.to_le_bytes());
+        hasher.write(&self.size.to_le_bytes());
+        hasher.finish()
+    }
+
+    /// Name of the thumbnail in the cache directory
+    fn file_name(&self) -> String {
+        format!("{:016x}.png", self.stable_hash())
+    }
+}
+
+/// Blocking access to the images of a source, implemented by the image sources
+/// to share the thumbnail cache
+pub trait ThumbnailSource {
+    /// Images of the source, like found by the last check
+    fn image_count(&self) -> anyhow::Result<usize>;
+    fn thumbnail_key(&self, index: usize) -> anyhow::Result<ThumbnailKey>;
+    fn decode_image(&self, index: usize, target: &DisplayTarget) -> anyhow::Result<DecodedImage>;
+}
+
+/// Thumbnails of the images of all the sources, stored in the cache directory of the user
+pub struct ThumbnailCache {}
+
+impl ThumbnailCache {
+    /// Read the thumbnail of an image from the cache, or create it
+    ///
+    /// This is a blocking call, don't use it on the event loop
+    pub fn get_or_create(
+        source: &impl ThumbnailSource,
+        index: usize,
+    ) -> anyhow::Result<DecodedImage> {
+        let path = Self::directory().join(source.thumbnail_key(index)?.file_name());
+        match std::fs::read(&path) {
+            Ok(bytes) => {
+                Self::mark_used(&path);
+                decoder::decode_image_from_memory(&bytes, &DisplayTarget::default())
+            }
+            Err(_) => {
+                let thumbnail = source.decode_image(index, &THUMBNAIL_TARGET)?;
+                Self::write(&path, &thumbnail)?;
+                Ok(thumbnail)
+            }
+        }
+    }
+
+    /// Create the missing thumbnails of the first `image_count` images of a source,
+    /// until `cancelled` is set
+    ///
+    /// The thumbnails used the longest time ago are removed once the cache exceeds
+    /// [`THUMBNAIL_CACHE_BUDGET`].
+    /// This is a blocking call, don't use it on the event loop
+    pub fn create_first(source: &impl ThumbnailSource, image_count: usize, cancelled: &AtomicBool) {
+        let image_count = match source.image_count() {
+            Ok(source_image_count) => source_image_count.min(image_count),
+            Err(error) => return Log::handle_error(error),
+        };
+
+        for index in 0..image_count {
+            if cancelled.load(Ordering::Acquire) {
+                return;
+            }
+
+            let result = source.thumbnail_key(index).and_then(|key| {
+                let path = Self::directory().join(key.file_name());
+                if path.is_file() {
+                    Self::mark_used(&path);
+                } else {
+                    Self::write(&path, &source.decode_image(index, &THUMBNAIL_TARGET)?)?;
+                }
+                Ok(())
+            });
+            // Undecodable images are already reported by the check
+            if let Err(error) = result {
+                Log::handle_error(error);
+            }
+        }
+
+        let result =
+            evict_least_recently_used(&Self::directory(), "png", THUMBNAIL_CACHE_BUDGET, |path| {
+                std::fs::remove_file(path)
+            });
+        if let Err(error) = result {
+            Log::handle_error(format!("Failed to clean the thumbnail cache: {}", error));
+        }
+    }
+
+    /// The modification date of a thumbnail tells when it was last used, see [`evict_least_recently_used`]
+    fn mark_used(path: &Path) {
+        // A thumbnail that can't be marked is only removed earlier
+        let _ = File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+    }
+
+    /// Write aside then rename, a reader never sees a partial thumbnail, see [`AsideFile`]
+    ///
+    /// [`AsideFile`]: crate::app::atomic_file::AsideFile
+    fn write(path: &Path, thumbnail: &DecodedImage) -> anyhow::Result<()> {
+        let image = RgbaImage::from_raw(
+            thumbnail.width(),
+            thumbnail.height(),
+            thumbnail.as_bytes().to_vec(),
+        )
+        .ok_or(anyhow::anyhow!("Invalid thumbnail buffer"))?;
+
+        let mut content = Vec::new();
+        image.write_to(&mut Cursor::new(&mut content), ImageFormat::Png)?;
+        // The same thumbnail may be created by two jobs, each writes its own file
+        atomic_file::write_atomically(path, &content)?;
+
+        Ok(())
+    }
+
+    fn directory() -> PathBuf {
+        cache_directory().join("thumbnails")
+    }
+}
+
+/// Background jobs filling the thumbnail cache, at most one per source
+#[derive(Default)]
+pub struct ThumbnailJobs {
+    cancel_flags: HashMap<Uuid, Arc<AtomicBool>>,
+}
+
+impl ThumbnailJobs {
+    /// Cancel the running job of a source, returns the cancel flag of its next job
+    pub fn restart(&mut self, uuid: Uuid) -> Arc<AtomicBool> {
+        self.cancel(uuid);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.cancel_flags.insert(uuid, cancelled.clone());
+        cancelled
+    }
+
+    pub fn cancel(&mut self, uuid: Uuid) {
+        if let Some(cancelled) = self.cancel_flags.remove(&uuid) {
+            cancelled.store(true, Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn key_changes_with_the_file() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("image.png");
+        std::fs::write(&path, [0; 10]).unwrap();
+
+        let key = ThumbnailKey::from_path(&path).unwrap();
+        assert_eq!(ThumbnailKey::from_path(&path).unwrap(), key);
+        assert_eq!(
+            ThumbnailKey::from_path(&path).unwrap().stable_hash(),
+            key.stable_hash()
+        );
+
+        // Same modification date, other size
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        std::fs::write(&path, [0; 20]).unwrap();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        let resized = ThumbnailKey::from_path(&path).unwrap();
+        assert_ne!(resized, key);
+        assert_ne!(resized.file_name(), key.file_name());
+
+        // Same size, other modification date
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified + Duration::from_secs(1))
+            .unwrap();
+        let touched = ThumbnailKey::from_path(&path).unwrap();
+        assert_ne!(touched, resized);
+        assert_ne!(touched.file_name(), resized.file_name());
+    }
+
+    #[test]
+    fn key_of_archive_entries() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("images.zip");
+        std::fs::write(&path, [0; 10]).unwrap();
+        let key = ThumbnailKey::from_path(&path).unwrap();
+
+        let first = key.clone().with_entry("a.png");
+        let second = key.clone().with_entry("b.png");
+        assert_ne!(first.file_name(), key.file_name());
+        assert_ne!(first.file_name(), second.file_name());
+        assert_eq!(first, key.with_entry("a.png"));
+    }
+
+    #[test]
+    fn missing_file_has_no_key() {
+        let directory = TempDir::new().unwrap();
+        assert!(ThumbnailKey::from_path(&directory.path().join("missing.png")).is_err());
+    }
+}
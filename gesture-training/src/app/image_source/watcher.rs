@@ -0,0 +1,246 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_std::channel::{Receiver, Sender};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use uuid::Uuid;
+
+use crate::app::log::Log;
+
+/// Time without changes to wait before reporting a modified source,
+/// copying a batch of images sends many events
+const WATCH_DEBOUNCE_DELAY: Duration = Duration::from_secs(1);
+/// Longest time changes are grouped, a source changed without pause is still checked
+const WATCH_DEBOUNCE_MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Path of a source to watch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchedPath {
+    /// Any change inside the folder and its subfolders
+    Folder(PathBuf),
+    /// Changes of a single file, like an image list
+    ///
+    /// Its folder is watched, editors and version control replace the file when they save it
+    /// and the watch of the replaced file would stop reporting changes.
+    File(PathBuf),
+}
+
+impl WatchedPath {
+    /// Path given to the watcher and how
+    fn watch(&self) -> (&Path, RecursiveMode) {
+        match self {
+            WatchedPath::Folder(path) => (path, RecursiveMode::Recursive),
+            WatchedPath::File(path) => (
+                path.parent().unwrap_or(Path::new(".")),
+                RecursiveMode::NonRecursive,
+            ),
+        }
+    }
+
+    fn is_modified_by(&self, event_path: &Path) -> bool {
+        match self {
+            WatchedPath::Folder(path) => event_path.starts_with(path),
+            WatchedPath::File(path) => event_path == path,
+        }
+    }
+}
+
+/// Sources watching the same path, the watch is kept until the last of them stops
+#[derive(Default)]
+struct WatchCount {
+    recursive: usize,
+    non_recursive: usize,
+}
+
+impl WatchCount {
+    fn mode(&self) -> Option<RecursiveMode> {
+        if self.recursive > 0 {
+            Some(RecursiveMode::Recursive)
+        } else if self.non_recursive > 0 {
+            Some(RecursiveMode::NonRecursive)
+        } else {
+            None
+        }
+    }
+
+    fn count_mut(&mut self, mode: RecursiveMode) -> &mut usize {
+        match mode {
+            RecursiveMode::Recursive => &mut self.recursive,
+            RecursiveMode::NonRecursive => &mut self.non_recursive,
+        }
+    }
+}
+
+/// Watch the folders and files of image sources and report the sources whose files changed
+pub struct ImageSourceWatcher {
+    watcher: RecommendedWatcher,
+    /// Watched path of each source, shared with the notify thread
+    watched: Arc<Mutex<HashMap<Uuid, WatchedPath>>>,
+    /// Paths given to the watcher, several sources may watch the same folder
+    watch_counts: HashMap<PathBuf, WatchCount>,
+}
+
+impl ImageSourceWatcher {
+    /// The ids of the modified sources are sent to the returned receiver,
+    /// see [`ImageSourceWatcher::next_modified_sources`]
+    pub fn new() -> anyhow::Result<(Self, Receiver<Uuid>)> {
+        let watched = Arc::new(Mutex::new(HashMap::<Uuid, WatchedPath>::new()));
+        let (sender, receiver) = async_std::channel::unbounded();
+
+        let watcher = {
+            let watched = watched.clone();
+            notify::recommended_watcher(move |event: notify::Result<Event>| match event {
+                Ok(event) => Self::handle_event(&watched, &sender, event),
+                Err(error) => Log::handle_error(error),
+            })?
+        };
+
+        Ok((
+            Self {
+                watcher,
+                watched,
+                watch_counts: HashMap::new(),
+            },
+            receiver,
+        ))
+    }
+
+    fn handle_event(
+        watched: &Mutex<HashMap<Uuid, WatchedPath>>,
+        sender: &Sender<Uuid>,
+        event: Event,
+    ) {
+        // Reading the images, to check the source or during a session, must not trigger a check
+        if matches!(event.kind, EventKind::Access(_)) {
+            return;
+        }
+
+        let watched = watched.lock().unwrap_or_else(|error| error.into_inner());
+        for (uuid, path) in watched.iter() {
+            if event.paths.iter().any(|event_path| path.is_modified_by(event_path)) {
+                // The receiver lives as long as the application
+                let _ = sender.try_send(*uuid);
+            }
+        }
+    }
+
+    /// Watch the given sources and stop watching the other ones
+    pub fn update(&mut self, sources: impl IntoIterator<Item = (Uuid, WatchedPath)>) {
+        let sources = sources.into_iter().collect::<HashMap<_, _>>();
+
+        let watched = self.watched.clone();
+        let mut watched = watched.lock().unwrap_or_else(|error| error.into_inner());
+
+        let removed = watched
+            .iter()
+            .filter(|(uuid, path)| sources.get(uuid) != Some(path))
+            .map(|(uuid, path)| (*uuid, path.clone()))
+            .collect::<Vec<_>>();
+        for (uuid, path) in removed {
+            watched.remove(&uuid);
+            let (watch_path, mode) = path.watch();
+            // Only fails to watch the folder again for the sources left, like a removed folder
+            if let Err(error) = self.update_watch_count(watch_path, mode, false) {
+                Log::handle_error(format!(
+                    "Failed to watch {}: {}",
+                    watch_path.display(),
+                    error
+                ));
+            }
+        }
+
+        for (uuid, path) in sources {
+            if watched.contains_key(&uuid) {
+                continue;
+            }
+            let (watch_path, mode) = path.watch();
+            match self.update_watch_count(watch_path, mode, true) {
+                Ok(()) => {
+                    watched.insert(uuid, path);
+                }
+                Err(error) => Log::handle_error(format!(
+                    "Failed to watch {}: {}",
+                    watch_path.display(),
+                    error
+                )),
+            }
+        }
+    }
+
+    /// Count a source watching `path` with `mode`, or stop counting it
+    ///
+    /// The path is watched again when the mode of its sources changes,
+    /// recursively when any of them needs it
+    fn update_watch_count(
+        &mut self,
+        path: &Path,
+        mode: RecursiveMode,
+        is_watched: bool,
+    ) -> notify::Result<()> {
+        let watch_count = self.watch_counts.entry(path.to_path_buf()).or_default();
+        let previous_mode = watch_count.mode();
+        let count = watch_count.count_mut(mode);
+        if is_watched {
+            *count += 1;
+        } else {
+            *count = count.saturating_sub(1);
+        }
+        let next_mode = watch_count.mode();
+        if next_mode.is_none() {
+            self.watch_counts.remove(path);
+        }
+        if next_mode == previous_mode {
+            return Ok(());
+        }
+
+        if previous_mode.is_some() {
+            // The folder may not exist anymore
+            let _ = self.watcher.unwatch(path);
+        }
+        let Some(next_mode) = next_mode else {
+            return Ok(());
+        };
+        let result = self.watcher.watch(path, next_mode);
+        if result.is_err() {
+            match (is_watched, self.watch_counts.get_mut(path)) {
+                // The source is not watched, the other sources keep their watch
+                (true, Some(watch_count)) => {
+                    *watch_count.count_mut(mode) -= 1;
+                    if let Some(previous_mode) = previous_mode {
+                        let _ = self.watcher.watch(path, previous_mode);
+                    } else {
+                        self.watch_counts.remove(path);
+                    }
+                }
+                // Like a removed folder, nothing is watched there anymore
+                _ => {
+                    self.watch_counts.remove(path);
+                }
+            }
+        }
+        result
+    }
+
+    /// Wait for sources to be modified, changes are grouped until none happened
+    /// during [`WATCH_DEBOUNCE_DELAY`], for [`WATCH_DEBOUNCE_MAX_DELAY`] at most
+    ///
+    /// Returns `None` once the watcher is dropped
+    pub async fn next_modified_sources(receiver: &Receiver<Uuid>) -> Option<HashSet<Uuid>> {
+        let mut modified = HashSet::from([receiver.recv().await.ok()?]);
+
+        let started_at = Instant::now();
+        while started_at.elapsed() < WATCH_DEBOUNCE_MAX_DELAY {
+            match async_std::future::timeout(WATCH_DEBOUNCE_DELAY, receiver.recv()).await {
+                Ok(Ok(uuid)) => {
+                    modified.insert(uuid);
+                }
+                // No change during the delay, or the watcher was dropped
+                Ok(Err(_)) | Err(_) => break,
+            }
+        }
+
+        Some(modified)
+    }
+}
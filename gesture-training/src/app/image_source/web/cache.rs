@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use url::Url;
+
+use crate::app::atomic_file::AsideFile;
+use crate::app::image_source::decoder::{self, FileKind};
+use crate::app::image_source::web::manifest;
+use crate::app::stable_hash::stable_hash;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Whole download of a file, large images on a slow connection take a while
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Downloaded files of a web source, kept to use the source offline
+///
+/// Images are only downloaded when they are first displayed
+#[derive(Debug, Clone)]
+pub struct WebCache {
+    directory: PathBuf,
+}
+
+impl WebCache {
+    /// Files of the manifest at `manifest_url`, inside `cache_directory`
+    pub fn new(cache_directory: &Path, manifest_url: &Url) -> Self {
+        Self {
+            directory: cache_directory
+                .join("web")
+                .join(Self::file_name(manifest_url)),
+        }
+    }
+
+    fn file_name(url: &Url) -> String {
+        format!("{:016x}", stable_hash(url.as_str().as_bytes()))
+    }
+
+    /// Last manifest downloaded
+    pub fn manifest_path(&self) -> PathBuf {
+        self.directory.join("manifest.json")
+    }
+
+    /// Present while the last check could not download the manifest
+    fn offline_marker_path(&self) -> PathBuf {
+        self.directory.join("offline")
+    }
+
+    /// Whether the last check used the cached manifest because the download failed
+    pub fn is_offline(&self) -> bool {
+        self.offline_marker_path().is_file()
+    }
+
+    /// Remember the result of the last manifest download, see [`WebCache::is_offline`]
+    pub fn set_offline(&self, is_offline: bool) -> std::io::Result<()> {
+        let path = self.offline_marker_path();
+        if is_offline {
+            std::fs::create_dir_all(&self.directory)?;
+            File::create(path)?;
+        } else if path.is_file() {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn image_path(&self, url: &Url) -> PathBuf {
+        self.directory.join(Self::file_name(url))
+    }
+
+    pub fn is_image_cached(&self, url: &Url) -> bool {
+        self.image_path(url).is_file()
+    }
+
+    /// Download the manifest again, the cached one is kept when it fails
+    /// or when the download is not a valid manifest, like an error page
+    ///
+    /// This is a blocking call, don't use it on the event loop
+    pub fn download_manifest(&self, url: &Url) -> anyhow::Result<()> {
+        Self::download(url, &self.manifest_path(), |path| {
+            let content = std::fs::read_to_string(path)?;
+            manifest::parse_manifest(url, &content).map(|_| ())
+        })
+    }
+
+    /// Path of the cached image, downloaded first if needed
+    ///
+    /// Downloads that are not images, like an error page, are not cached
+    ///
+    /// This is a blocking call, don't use it on the event loop
+    pub fn get_or_download_image(&self, url: &Url) -> anyhow::Result<PathBuf> {
+        let path = self.image_path(url);
+        if !path.is_file() {
+            Self::download(url, &path, |path| match decoder::classify_path(path)? {
+                FileKind::Image => Ok(()),
+                _ => Err(anyhow::anyhow!("{} is not an image", url)),
+            })?;
+        }
+        Ok(path)
+    }
+
+    /// Forget a cached image that can't be decoded, it is downloaded again when next loaded
+    pub fn remove_image(&self, url: &Url) -> std::io::Result<()> {
+        std::fs::remove_file(self.image_path(url))
+    }
+
+    /// Write aside then rename, a reader never sees a partial file, see [`AsideFile`]
+    ///
+    /// The file is only renamed into place once `validate` accepted it,
+    /// so the cached copy is never replaced by an invalid one
+    fn download(
+        url: &Url,
+        path: &Path,
+        validate: impl FnOnce(&Path) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(CONNECT_TIMEOUT)
+            .timeout(DOWNLOAD_TIMEOUT)
+            .build();
+        let response = agent
+            .request_url("GET", url)
+            .call()
+            // The error already names the URL
+            .map_err(|error| anyhow::anyhow!("Failed to download {}", error))?;
+
+        // The same image may be downloaded by the session and a thumbnail, each writes its own file
+        let result = AsideFile::create(path)
+            .and_then(|mut file| {
+                std::io::copy(&mut response.into_reader(), &mut file)?;
+                Ok(file)
+            })
+            .map_err(anyhow::Error::from)
+            .and_then(|file| {
+                validate(file.temporary_path())?;
+                Ok(file.persist()?)
+            });
+        if let Err(error) = result {
+            return Err(anyhow::anyhow!("Failed to download {}: {}", url, error));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,407 @@
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+
+use async_std::task;
+use serde::{Deserialize, Serialize};
+use slint::{Image, SharedString};
+use url::Url;
+use uuid::Uuid;
+
+use crate::app::image_source::decoder::{self, DecodedImage, SkippedFiles};
+use crate::app::image_source::index::{FileStamp, ImageIndex, ImageIndexCache};
+use crate::app::image_source::thumbnail::{ThumbnailCache, ThumbnailKey, ThumbnailSource};
+use crate::app::image_source::web::cache::WebCache;
+use crate::app::image_source::web::manifest;
+use crate::app::image_source::{
+    cache_directory, default_weight, DisplayTarget, ImageSource, ImageSourceCheck,
+    ImageSourceStatus, ImageSourceTrait,
+};
+use crate::sg;
+
+/// Use the images listed by a JSON manifest on a web server as an image source
+///
+/// The manifest is downloaded again on each check and images when they are first loaded.
+/// Both are kept in the cache directory, so the source still works offline
+/// with the images already downloaded.
+/// Images are not downloaded by the check, so they are never found as duplicates.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageSourceWeb {
+    id: Uuid,
+    pub(crate) name: String,
+    /// URL of the manifest
+    pub(crate) url: String,
+    /// Relative chance to pick this source with the weighted selection strategy
+    #[serde(default = "default_weight")]
+    pub(crate) weight: u32,
+    /// Last check, displayed until the source is checked again
+    #[serde(default)]
+    check: ImageSourceCheck,
+    /// Built from the cached manifest
+    #[serde(skip)]
+    index: ImageIndexCache<Url>,
+    /// Where the manifest and the images are downloaded, the user cache outside of tests
+    #[serde(skip, default = "cache_directory")]
+    cache_directory: PathBuf,
+}
+
+impl ImageSourceWeb {
+    pub fn new(id: Uuid, name: String, url: String, check: ImageSourceCheck) -> Self {
+        Self {
+            id,
+            name,
+            url,
+            weight: default_weight(),
+            check,
+            index: ImageIndexCache::default(),
+            cache_directory: cache_directory(),
+        }
+    }
+
+    fn manifest_url(&self) -> anyhow::Result<Url> {
+        Url::parse(self.url.trim())
+            .map_err(|error| anyhow::anyhow!("Invalid manifest URL {}: {}", self.url, error))
+    }
+
+    /// List the images of the cached manifest
+    ///
+    /// Offline, only the images already downloaded are used, the other ones are counted as missing.
+    /// The offline state of the last check is read from the cache, so an index built again
+    /// later has the images at the same positions
+    fn build_index(
+        manifest_url: &Url,
+        cache: &WebCache,
+    ) -> anyhow::Result<(ImageIndex<Url>, SkippedFiles)> {
+        let manifest_path = cache.manifest_path();
+        let content = std::fs::read_to_string(&manifest_path)?;
+        let mut urls = manifest::parse_manifest(manifest_url, &content)?;
+
+        let mut skipped_files = SkippedFiles::default();
+        if cache.is_offline() {
+            let listed_count = urls.len();
+            urls.retain(|url| cache.is_image_cached(url));
+            skipped_files.missing = listed_count - urls.len();
+        }
+
+        let stamps = vec![FileStamp::new(&manifest_path)];
+        Ok((ImageIndex::new(manifest_path, urls, stamps), skipped_files))
+    }
+
+    /// Blocking part of [`ImageSourceTrait::check_source`]
+    fn scan(&self) -> ImageSourceCheck {
+        fn execute(this: &ImageSourceWeb) -> anyhow::Result<ImageSourceCheck> {
+            let manifest_url = this.manifest_url()?;
+            let cache = WebCache::new(&this.cache_directory, &manifest_url);
+
+            let download_error = cache.download_manifest(&manifest_url).err();
+            // Offline, fall back on the last manifest downloaded
+            let offline_warning = match download_error {
+                Some(error) if cache.manifest_path().is_file() => Some(format!(
+                    "Offline, using the images downloaded before: {}",
+                    error
+                )),
+                Some(error) => return Err(error),
+                None => None,
+            };
+            let is_offline = offline_warning.is_some();
+            cache.set_offline(is_offline)?;
+
+            let (index, skipped_files) = ImageSourceWeb::build_index(&manifest_url, &cache)?;
+            if is_offline && index.len() == 0 {
+                return Err(anyhow::anyhow!(
+                    "Failed to download {} and none of its images were downloaded before",
+                    manifest_url
+                ));
+            }
+
+            let index = Arc::new(index);
+            this.index.set(index.clone());
+            let check = ImageSourceCheck::new(index.len(), ImageSourceStatus::Valid)
+                .with_skipped_files(skipped_files);
+            Ok(match offline_warning {
+                Some(warning) => check.with_warning(warning),
+                None => check,
+            })
+        }
+
+        execute(self).unwrap_or_else(|error| {
+            ImageSourceCheck::new(0, ImageSourceStatus::Error(error.to_string()))
+        })
+    }
+
+    fn image_index(&self) -> anyhow::Result<(Arc<ImageIndex<Url>>, WebCache)> {
+        let manifest_url = self.manifest_url()?;
+        let cache = WebCache::new(&self.cache_directory, &manifest_url);
+        let index = self.index.get_or_build(&cache.manifest_path(), || {
+            Self::build_index(&manifest_url, &cache).map(|(index, _)| index)
+        })?;
+        Ok((index, cache))
+    }
+
+    fn image_url(&self, index: usize) -> anyhow::Result<(Url, WebCache)> {
+        let (image_index, cache) = self.image_index()?;
+        let url = image_index
+            .get(index)
+            .cloned()
+            .ok_or(anyhow::anyhow!("No image at index {} in manifest", index))?;
+        Ok((url, cache))
+    }
+
+    /// Path of the downloaded image, downloaded first if needed
+    fn image_path(&self, index: usize) -> anyhow::Result<PathBuf> {
+        let (url, cache) = self.image_url(index)?;
+        cache.get_or_download_image(&url)
+    }
+}
+
+impl ThumbnailSource for ImageSourceWeb {
+    fn image_count(&self) -> anyhow::Result<usize> {
+        Ok(self.image_index()?.0.len())
+    }
+
+    /// Downloads the image if needed
+    fn thumbnail_key(&self, index: usize) -> anyhow::Result<ThumbnailKey> {
+        Ok(ThumbnailKey::from_path(&self.image_path(index)?)?)
+    }
+
+    /// Blocking part of [`ImageSourceTrait::load_image`]
+    fn decode_image(&self, index: usize, target: &DisplayTarget) -> anyhow::Result<DecodedImage> {
+        let (url, cache) = self.image_url(index)?;
+        let path = cache.get_or_download_image(&url)?;
+        decoder::decode_image_from_path(&path, target).inspect_err(|_| {
+            // Like a truncated download, the server may send it whole next time
+            let _ = cache.remove_image(&url);
+        })
+    }
+}
+
+impl ImageSourceTrait for ImageSourceWeb {
+    fn id(&self) -> Uuid {
+        self.id
+    }
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn weight(&self) -> u32 {
+        self.weight
+    }
+
+    fn check(&self) -> &ImageSourceCheck {
+        &self.check
+    }
+
+    fn set_check(&mut self, check: ImageSourceCheck) {
+        self.check = check;
+    }
+
+    async fn check_source(&self) -> ImageSourceCheck {
+        let this = self.clone();
+        task::spawn_blocking(move || this.scan()).await
+    }
+
+    async fn load_image(&self, index: usize, target: DisplayTarget) -> anyhow::Result<Image> {
+        let this = self.clone();
+        let buffer = task::spawn_blocking(move || this.decode_image(index, &target)).await?;
+        Ok(Image::from_rgba8(buffer))
+    }
+
+    async fn load_thumbnail(&self, index: usize) -> anyhow::Result<Image> {
+        let this = self.clone();
+        let buffer =
+            task::spawn_blocking(move || ThumbnailCache::get_or_create(&this, index)).await?;
+        Ok(Image::from_rgba8(buffer))
+    }
+
+    /// Images are only downloaded on demand, their thumbnails are created when they are loaded
+    async fn create_thumbnails(&self, _image_count: usize, _cancelled: Arc<AtomicBool>) {}
+}
+
+impl<'a> From<&'a ImageSourceWeb> for sg::EditSourceWebData {
+    fn from(value: &'a ImageSourceWeb) -> Self {
+        Self {
+            id: value.id.to_string().into(),
+            name: value.name.clone().into(),
+            image_count: 0,
+            url: value.url.clone().into(),
+            weight: value.weight as i32,
+            status: sg::StatusIconData {
+                r#type: sg::StatusIconType::Unknown,
+                error: SharedString::default(),
+            },
+        }
+    }
+}
+
+impl From<ImageSourceWeb> for sg::EditSourceWebData {
+    fn from(value: ImageSourceWeb) -> Self {
+        (&value).into()
+    }
+}
+
+impl TryFrom<ImageSource> for sg::EditSourceWebData {
+    type Error = anyhow::Error;
+    fn try_from(value: ImageSource) -> Result<Self, Self::Error> {
+        match value {
+            ImageSource::Web(web) => Ok(web.into()),
+            _ => Err(anyhow::anyhow!("Image source is not a web source")),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread::JoinHandle;
+
+    use tempfile::TempDir;
+
+    const MANIFEST: &str =
+        r#"{ "images": ["first.png", { "url": "second.png", "title": "Second" }] }"#;
+
+    /// Smallest valid image
+    fn png() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        image::RgbaImage::new(1, 1)
+            .write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    /// Serve the manifest and its images
+    fn respond_with_images(path: &str) -> (&'static str, Vec<u8>) {
+        match path {
+            "/manifest.json" => ("200 OK", MANIFEST.as_bytes().to_vec()),
+            "/first.png" | "/second.png" => ("200 OK", png()),
+            _ => ("404 Not Found", Vec::new()),
+        }
+    }
+
+    /// Answer `request_count` requests with the status and the body `respond` returns
+    /// for their path, then stop listening
+    fn serve(
+        request_count: usize,
+        mut respond: impl FnMut(&str) -> (&'static str, Vec<u8>) + Send + 'static,
+    ) -> (SocketAddr, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            for stream in listener.incoming().take(request_count) {
+                let mut stream = stream.unwrap();
+                let mut request_line = String::new();
+                let mut reader = BufReader::new(&stream);
+                reader.read_line(&mut request_line).unwrap();
+                // Skip the headers
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+                let (status, body) = respond(path);
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                )
+                .unwrap();
+                stream.write_all(&body).unwrap();
+            }
+        });
+        (address, server)
+    }
+
+    /// Source downloading to `cache_directory`, out of the user cache
+    fn web_source(address: SocketAddr, cache_directory: &TempDir) -> ImageSourceWeb {
+        let mut source = ImageSourceWeb::new(
+            Uuid::new_v4(),
+            "Test".to_string(),
+            format!("http://{}/manifest.json", address),
+            ImageSourceCheck::default(),
+        );
+        source.cache_directory = cache_directory.path().to_path_buf();
+        source
+    }
+
+    #[test]
+    fn online_then_offline() {
+        let cache_directory = TempDir::new().unwrap();
+        let (address, server) = serve(2, respond_with_images);
+        let source = web_source(address, &cache_directory);
+
+        let check = source.scan();
+        assert_eq!(check.status(), &ImageSourceStatus::Valid);
+        assert_eq!(check.image_count(), 2);
+        assert_eq!(check.skipped_files_message(), None);
+        assert_eq!(check.warning(), None);
+
+        let image_path = source.image_path(1).unwrap();
+        assert_eq!(std::fs::read(image_path).unwrap(), png());
+        server.join().unwrap();
+
+        // Only the downloaded image is left once the server is gone
+        let check = source.scan();
+        assert_eq!(check.status(), &ImageSourceStatus::Valid);
+        assert!(check.warning().is_some_and(|warning| warning.starts_with("Offline")));
+        assert_eq!(check.image_count(), 1);
+        assert_eq!(
+            check.skipped_files_message().as_deref(),
+            Some("1 listed images not available")
+        );
+
+        // A source built again from the cache, like on the next launch, agrees with the check
+        let source = web_source(address, &cache_directory);
+        let (index, _) = source.image_index().unwrap();
+        assert_eq!(index.len(), 1);
+        assert_eq!(index.get(0).map(Url::path), Some("/second.png"));
+    }
+
+    #[test]
+    fn error_pages_are_not_cached() {
+        let cache_directory = TempDir::new().unwrap();
+        let mut manifest_request_count = 0;
+        let (address, server) = serve(4, move |path| match path {
+            "/manifest.json" if manifest_request_count > 0 => {
+                ("200 OK", b"<html>Maintenance</html>".to_vec())
+            }
+            "/manifest.json" => {
+                manifest_request_count += 1;
+                ("200 OK", MANIFEST.as_bytes().to_vec())
+            }
+            "/first.png" => ("200 OK", b"<html>Not found</html>".to_vec()),
+            path => respond_with_images(path),
+        });
+        let source = web_source(address, &cache_directory);
+
+        let check = source.scan();
+        assert_eq!(check.image_count(), 2);
+        assert!(source.image_path(0).is_err());
+        assert!(source.image_path(1).is_ok());
+
+        // The last valid manifest is kept, with the only image downloaded
+        let check = source.scan();
+        server.join().unwrap();
+        assert_eq!(check.status(), &ImageSourceStatus::Valid);
+        assert!(check.warning().is_some());
+        assert_eq!(check.image_count(), 1);
+        let (index, _) = source.image_index().unwrap();
+        assert_eq!(index.get(0).map(Url::path), Some("/second.png"));
+    }
+
+    #[test]
+    fn offline_without_cached_manifest() {
+        let cache_directory = TempDir::new().unwrap();
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let source = web_source(address, &cache_directory);
+
+        let check = source.scan();
+        assert!(matches!(check.status(), ImageSourceStatus::Error(_)));
+        assert_eq!(check.image_count(), 0);
+    }
+}
@@ -0,0 +1,50 @@
+use serde::Deserialize;
+use url::Url;
+
+/// Document listing the images of a web source
+///
+/// Either a JSON array, or an object with an `images` array. Each image is its URL,
+/// or an object with a `url` field; its other fields, like a title or an author,
+/// are accepted and ignored. URLs are relative to the manifest unless absolute.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ManifestDocument {
+    Images(Vec<ManifestImage>),
+    Object { images: Vec<ManifestImage> },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ManifestImage {
+    Url(String),
+    Object { url: String },
+}
+
+impl ManifestImage {
+    fn url(&self) -> &str {
+        match self {
+            ManifestImage::Url(url) => url,
+            ManifestImage::Object { url } => url,
+        }
+    }
+}
+
+/// Parse a manifest downloaded from `manifest_url`, returns the URLs of its images
+/// in the order of the manifest
+pub fn parse_manifest(manifest_url: &Url, content: &str) -> anyhow::Result<Vec<Url>> {
+    let document = serde_json::from_str::<ManifestDocument>(content)
+        .map_err(|error| anyhow::anyhow!("Invalid manifest {}: {}", manifest_url, error))?;
+    let images = match document {
+        ManifestDocument::Images(images) => images,
+        ManifestDocument::Object { images } => images,
+    };
+
+    images
+        .iter()
+        .map(|image| {
+            manifest_url
+                .join(image.url())
+                .map_err(|error| anyhow::anyhow!("Invalid image URL {}: {}", image.url(), error))
+        })
+        .collect()
+}
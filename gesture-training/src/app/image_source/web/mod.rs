@@ -0,0 +1,5 @@
+pub use image_source::ImageSourceWeb;
+
+mod cache;
+mod image_source;
+mod manifest;
@@ -1,29 +1,48 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use uuid::Uuid;
+
 use crate::app::app_ui::AppUi;
+use crate::app::image_source::archive::AppSourceArchive;
 use crate::app::image_source::folder::AppSourceFolder;
+use crate::app::image_source::list::AppSourceList;
+use crate::app::image_source::{ImageSourceWatcher, ThumbnailJobs};
 use crate::app::session::AppSession;
 
 mod app_callback;
+mod atomic_file;
 mod app_ui;
 mod backend;
 mod image_source;
 mod log;
 mod session;
+mod stable_hash;
 #[cfg(target_os = "android")]
 pub mod android_support;
 
 pub struct App {
     source_folder: AppSourceFolder,
+    source_archive: AppSourceArchive,
+    source_list: AppSourceList,
     session: AppSession,
+    /// `None` when the platform can't watch folders
+    image_source_watcher: Option<ImageSourceWatcher>,
+    thumbnail_jobs: ThumbnailJobs,
+    /// Source whose thumbnails are displayed by the opened editor
+    thumbnail_source_id: Option<Uuid>,
 }
 
 impl App {
     pub fn new() -> Self {
         Self {
             source_folder: AppSourceFolder::new(),
+            source_archive: AppSourceArchive::new(),
+            source_list: AppSourceList::new(),
             session: AppSession::new(),
+            image_source_watcher: None,
+            thumbnail_jobs: ThumbnailJobs::default(),
+            thumbnail_source_id: None,
         }
     }
 
@@ -44,4 +63,20 @@ impl App {
     fn source_folder_mut(&mut self) -> &mut AppSourceFolder {
         &mut self.source_folder
     }
+
+    fn source_archive(&self) -> &AppSourceArchive {
+        &self.source_archive
+    }
+
+    fn source_archive_mut(&mut self) -> &mut AppSourceArchive {
+        &mut self.source_archive
+    }
+
+    fn source_list(&self) -> &AppSourceList {
+        &self.source_list
+    }
+
+    fn source_list_mut(&mut self) -> &mut AppSourceList {
+        &mut self.source_list
+    }
 }
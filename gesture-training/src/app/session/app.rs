@@ -1,60 +1,133 @@
 use std::cell::RefCell;
-use std::sync::Arc;
+use std::collections::{HashSet, VecDeque};
+use std::rc::Rc;
 use std::time::{Duration, Instant};
 
-use rand::Rng;
 use slint::{Timer, TimerMode};
+use uuid::Uuid;
 use crate::app::backend::{AppBackendModifications, SessionModification};
-use crate::app::image_source::{ImageSource, ImageSourceTrait};
-use crate::app::log::Log;
+use crate::app::image_source::{find_duplicate_images, DisplayTarget, ImageSource, ImageSourceTrait};
+use crate::app::session::plan::SessionPlan;
+use crate::app::session::prefetch::{ImagePrefetcher, PREFETCH_IMAGE_COUNT};
+use crate::app::session::selection::{ImageSelector, SelectionStrategy};
 use crate::sg;
 
+/// Number of previous sessions whose images are only shown again
+/// once all the other images were shown, when the session avoids recent images
+const RECENT_SESSION_COUNT: usize = 3;
+
 #[derive(Debug, Clone)]
 pub struct AppSessionConfiguration {
-    image_duration: Duration,
-    image_count: usize,
+    plan: SessionPlan,
     image_sources: Vec<ImageSource>,
+    selection_strategy: SelectionStrategy,
+    recent_session_count: usize,
+    /// Seed of the image selection, random when `None`
+    seed: Option<u64>,
+    /// Images are downscaled to it when decoded
+    display_target: DisplayTarget,
+    /// Copies of images of the sources before them, never shown
+    duplicate_images: HashSet<ImageCoordinate>,
 }
 
 impl AppSessionConfiguration {
     pub fn new(
-        image_duration: Duration,
-        image_count: usize,
-        image_sources: Vec<ImageSource>,
+        plan: SessionPlan,
+        mut image_sources: Vec<ImageSource>,
+        selection_strategy: SelectionStrategy,
+        avoid_recent_images: bool,
+        seed: Option<u64>,
     ) -> Self {
+        // The selection depends on the order of the sources, keep it stable
+        // so a seed replays the same images
+        image_sources.sort_by(|left, right| {
+            left.name()
+                .cmp(right.name())
+                .then_with(|| left.id().cmp(&right.id()))
+        });
+
+        let duplicate_images = find_duplicate_images(image_sources.iter().map(ImageSource::check))
+            .into_iter()
+            .map(|(image_source_index, image_index)| ImageCoordinate {
+                image_source_index,
+                image_index,
+            })
+            .collect();
+
         Self {
-            image_duration,
-            image_count,
+            plan,
             image_sources,
+            selection_strategy,
+            // Recent images depend on the local history, seeded sessions ignore them
+            // so they replay the same images everywhere
+            recent_session_count: if seed.is_some() || !avoid_recent_images {
+                0
+            } else {
+                RECENT_SESSION_COUNT
+            },
+            seed,
+            display_target: DisplayTarget::default(),
+            duplicate_images,
         }
     }
+
+    pub fn with_display_target(mut self, display_target: DisplayTarget) -> Self {
+        self.display_target = display_target;
+        self
+    }
+
+    pub fn plan(&self) -> &SessionPlan {
+        &self.plan
+    }
 }
 
 #[derive(Default)]
 struct AppSessionCallbacks {
-    on_timer_tick: Option<Arc<dyn Fn(Duration) + 'static>>,
-    on_start_image_load: Option<Arc<dyn Fn() + 'static>>,
-    on_image_loaded: Option<Arc<dyn Fn(slint::Image) + 'static>>,
+    on_timer_tick: Option<Rc<dyn Fn(Duration) + 'static>>,
+    on_start_image_load: Option<Rc<dyn Fn() + 'static>>,
 }
 
 pub struct AppSession {
-    timer_tick: Arc<Timer>,
-    timer_data: Arc<RefCell<TimerData>>,
+    timer_tick: Rc<Timer>,
+    timer_data: Rc<RefCell<TimerData>>,
 
     config: Option<AppSessionConfiguration>,
     image_history: Vec<ImageCoordinate>,
     /// Index from the end of the history vector
     image_history_index: usize,
+    /// Images picked ahead of time, displayed after the history
+    upcoming_images: VecDeque<ImageCoordinate>,
+    image_selector: Option<ImageSelector>,
+    /// Seed replaying the current session, `None` when recent images changed the selection
+    replay_seed: Option<u64>,
+    /// A break is in progress, the next image is shown once it ends
+    is_on_break: bool,
+    /// Position of the image after which the last break was taken
+    last_break_position: Option<usize>,
+    /// Images shown during the previous sessions, most recent first
+    recent_sessions: VecDeque<HashSet<(Uuid, usize)>>,
+    prefetcher: Rc<RefCell<ImagePrefetcher>>,
     session_callbacks: AppSessionCallbacks,
 }
 
 impl AppSession {
     pub fn new() -> Self {
         Self {
-            timer_tick: Arc::new(Timer::default()),
-            timer_data: Arc::new(RefCell::new(TimerData::default())),
+            timer_tick: Rc::new(Timer::default()),
+            timer_data: Rc::new(RefCell::new(TimerData::default())),
             config: None,
             image_history: Vec::default(),
+            upcoming_images: VecDeque::default(),
+            image_selector: None,
+            replay_seed: None,
+            is_on_break: false,
+            last_break_position: None,
+            recent_sessions: VecDeque::default(),
+            prefetcher: Rc::new(RefCell::new(ImagePrefetcher::new(
+                None,
+                None,
+                DisplayTarget::default(),
+            ))),
             session_callbacks: AppSessionCallbacks::default(),
             image_history_index: 0,
         }
@@ -75,61 +148,191 @@ impl AppSession {
         on_timer_tick: impl Fn(Duration) + Clone + 'static,
         on_timer_timeout: impl Fn() + 'static,
         on_loading_image: impl Fn() + 'static,
-        on_image_loaded: impl Fn(slint::Image) + 'static,
+        on_image_loaded: impl Fn(slint::Image, Duration) + 'static,
+        on_image_failed: impl Fn() + 'static,
     ) -> anyhow::Result<()> {
         {
+            self.archive_session_history();
             self.config = Some(config.clone());
+            self.image_history.clear();
+            self.image_history_index = 0;
+            self.upcoming_images.clear();
+            self.is_on_break = false;
+            self.last_break_position = None;
+            let (image_selector, replay_seed) = self.new_image_selector(config);
+            self.image_selector = Some(image_selector);
+            self.replay_seed = replay_seed;
         }
 
-        self.session_callbacks.on_timer_tick = Some(Arc::new(on_timer_tick.clone()));
-        self.session_callbacks.on_start_image_load = Some(Arc::new(on_loading_image));
-        self.session_callbacks.on_image_loaded = Some(Arc::new(on_image_loaded));
+        self.session_callbacks.on_timer_tick = Some(Rc::new(on_timer_tick.clone()));
+        self.session_callbacks.on_start_image_load = Some(Rc::new(on_loading_image));
+
+        // Images still loading for a previous session must not be displayed
+        self.prefetcher.borrow_mut().clear();
+        let timer = self.timer_tick.clone();
+        let timer_data = self.timer_data.clone();
+        self.prefetcher = Rc::new(RefCell::new(ImagePrefetcher::new(
+            Some(Rc::new(move |image| {
+                timer.restart();
+                let image_duration = timer_data.borrow().image_duration;
+                on_image_loaded(image, image_duration);
+            })),
+            Some(Rc::new(on_image_failed)),
+            config.display_target,
+        )));
 
         self.configure_timer(on_timer_tick, on_timer_timeout)?;
 
-        self.go_to_next_image()?;
+        // The timeout would try to draw again without the first image
+        if let Err(error) = self.go_to_next_image() {
+            self.timer_tick.stop();
+            return Err(error);
+        }
 
         Ok(())
     }
 
+    /// Remember the images of the last session so the next sessions avoid them
+    fn archive_session_history(&mut self) {
+        if let Some(config) = self.config.as_ref() {
+            let images = self
+                .image_history
+                .iter()
+                .map(|image_coordinate| {
+                    (
+                        config.image_sources[image_coordinate.image_source_index].id(),
+                        image_coordinate.image_index,
+                    )
+                })
+                .collect();
+            self.recent_sessions.push_front(images);
+            self.recent_sessions.truncate(RECENT_SESSION_COUNT);
+        }
+    }
+
+    /// Seed to share to replay the current session
+    pub fn replay_seed(&self) -> Option<u64> {
+        self.replay_seed
+    }
+
+    /// Returns the selector and the seed replaying its images
+    fn new_image_selector(
+        &self,
+        config: &AppSessionConfiguration,
+    ) -> (ImageSelector, Option<u64>) {
+        let recent_images = self
+            .recent_sessions
+            .iter()
+            .take(config.recent_session_count)
+            .flatten()
+            .filter_map(|(uuid, image_index)| {
+                config
+                    .image_sources
+                    .iter()
+                    .position(|image_source| image_source.id() == *uuid)
+                    .map(|image_source_index| ImageCoordinate {
+                        image_source_index,
+                        image_index: *image_index,
+                    })
+            })
+            .collect::<HashSet<_>>();
+
+        let seed = config.seed.unwrap_or_else(rand::random);
+        // Only sessions avoiding recent images depend on more than the seed
+        let replay_seed = recent_images.is_empty().then_some(seed);
+
+        let image_selector = ImageSelector::new(
+            config.selection_strategy,
+            config.image_sources.iter().map(|image_source| {
+                (
+                    image_source.check().image_count(),
+                    image_source.weight() as f64,
+                )
+            }),
+            &recent_images,
+            &config.duplicate_images,
+            seed,
+        );
+
+        (image_selector, replay_seed)
+    }
+
     pub fn reset_time_left(&self) -> anyhow::Result<()> {
-        let config = self.config.as_ref().ok_or(anyhow::anyhow!(""))?;
         let mut timer_data_ref = self.timer_data.borrow_mut();
-        timer_data_ref.time_left = config.image_duration;
+        timer_data_ref.time_left = timer_data_ref.image_duration;
         timer_data_ref.last_tick_date = Instant::now();
 
         Ok(())
     }
 
     fn go_to_image(&self, image_coordinate: ImageCoordinate) -> anyhow::Result<()> {
-        let config = self.config.as_ref().ok_or(anyhow::anyhow!(""))?.clone();
-        let image_source = config.image_sources[image_coordinate.image_source_index].clone();
-        let timer = self.timer_tick.clone();
-
-        timer.stop();
-        if let Some(callback) = self.session_callbacks.on_start_image_load.as_ref() {
-            callback();
-        }
-        let on_image_loaded = self.session_callbacks.on_image_loaded.clone();
-        slint::spawn_local(async move {
-            match image_source.load_image(image_coordinate.image_index).await {
-                Ok(image) => {
-                    timer.restart();
-                    if let Some(callback) = on_image_loaded {
-                        let callback = callback.clone();
-                        callback(image);
-                    }
-                }
-                Err(error) => {
-                    Log::handle_error(&error);
-                }
+        let config = self.config.as_ref().ok_or(anyhow::anyhow!(""))?;
+        let image_source = &config.image_sources[image_coordinate.image_source_index];
+
+        self.timer_tick.stop();
+        // The duration changes with the segments of the plan
+        self.timer_data.borrow_mut().image_duration = config
+            .plan
+            .image_duration(self.current_image_position())
+            .unwrap_or_default();
+
+        // Decoding runs on a worker thread, the event loop only receives the pixels
+        let is_loaded = ImagePrefetcher::show(&self.prefetcher, image_coordinate, image_source)?;
+        if !is_loaded {
+            if let Some(callback) = self.session_callbacks.on_start_image_load.as_ref() {
+                callback();
             }
-        })?;
+        }
+
+        let next_images = self
+            .next_image_coordinates()
+            .take(PREFETCH_IMAGE_COUNT)
+            .map(|image_coordinate| {
+                (
+                    image_coordinate,
+                    config.image_sources[image_coordinate.image_source_index].clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        ImagePrefetcher::prefetch(&self.prefetcher, next_images)?;
 
         Ok(())
     }
 
+    /// Images shown since the start of the session, with their source
+    pub fn shown_images(&self) -> Vec<(ImageSource, usize)> {
+        self.config
+            .as_ref()
+            .map(|config| {
+                self.image_history
+                    .iter()
+                    .map(|image_coordinate| {
+                        (
+                            config.image_sources[image_coordinate.image_source_index].clone(),
+                            image_coordinate.image_index,
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Position of the displayed image in the session
+    fn current_image_position(&self) -> usize {
+        self.image_history.len().saturating_sub(1 + self.image_history_index)
+    }
+
+    /// Images that will be displayed after the current one
+    fn next_image_coordinates(&self) -> impl Iterator<Item = ImageCoordinate> + '_ {
+        let history_start = self.image_history.len() - self.image_history_index;
+        self.image_history[history_start..]
+            .iter()
+            .chain(self.upcoming_images.iter())
+            .copied()
+    }
+
     pub fn go_to_previous_image(&mut self) -> anyhow::Result<()> {
+        self.is_on_break = false;
         if let Some(image_coordinate) = self.session_previous_image_coordinates() {
             self.go_to_image(image_coordinate)?;
         }
@@ -137,7 +340,36 @@ impl AppSession {
         Ok(())
     }
 
+    /// Replace the displayed image by the next one when it fails to load
+    ///
+    /// The image is removed from the history so it doesn't count in the plan
+    pub fn skip_failed_image(&mut self) -> anyhow::Result<AppBackendModifications> {
+        if self.is_on_break {
+            return Ok(AppBackendModifications::default());
+        }
+        let Some(position) = self.displayed_history_position() else {
+            return Ok(AppBackendModifications::default());
+        };
+
+        self.image_history.remove(position);
+        if self.image_history_index == 0 {
+            self.go_to_next_image()
+        } else {
+            // The image after it in the history takes its position
+            self.image_history_index -= 1;
+            self.go_to_image(self.image_history[position])?;
+            Ok(AppBackendModifications::default())
+        }
+    }
+
     pub fn go_to_next_image(&mut self) -> anyhow::Result<AppBackendModifications> {
+        if !self.is_on_break {
+            if let Some(modifications) = self.start_break_if_due() {
+                return Ok(modifications);
+            }
+        }
+        self.is_on_break = false;
+
         match self.session_next_image_coordinates() {
             Ok(Some(image_coordinate)) => {
                 self.go_to_image(image_coordinate)?;
@@ -151,93 +383,152 @@ impl AppSession {
         }
     }
 
+    /// End the current break and show the next image
+    pub fn skip_break(&mut self) -> anyhow::Result<AppBackendModifications> {
+        if self.is_on_break {
+            self.go_to_next_image()
+        } else {
+            Ok(AppBackendModifications::default())
+        }
+    }
+
+    /// Start a break if the plan has one after the displayed image
+    ///
+    /// Breaks are only taken once, when leaving the last image of the history
+    fn start_break_if_due(&mut self) -> Option<AppBackendModifications> {
+        let config = self.config.as_ref()?;
+        if self.image_history.is_empty() || self.image_history_index != 0 {
+            return None;
+        }
+
+        let position = self.current_image_position();
+        if self.last_break_position == Some(position) {
+            return None;
+        }
+        let break_duration = config.plan.break_after(position)?;
+
+        self.is_on_break = true;
+        self.last_break_position = Some(position);
+        {
+            // The break counts down like an image
+            let mut timer_data = self.timer_data.borrow_mut();
+            timer_data.last_tick_date = Instant::now();
+            timer_data.image_duration = break_duration;
+            timer_data.time_left = break_duration;
+        }
+        self.timer_tick.restart();
+
+        Some(SessionModification::State(sg::SessionWindowState::Break).into())
+    }
+
     fn session_next_image_coordinates(&mut self) -> anyhow::Result<Option<ImageCoordinate>> {
         if self.image_history_index == 0 {
-            if self.image_history.len() == self.config.as_ref().unwrap().image_count {
-                Ok(None)
-            } else if let Some(image_coordinate) = self.find_next_image_coordinates() {
+            self.fill_upcoming_images();
+            if let Some(image_coordinate) = self.upcoming_images.pop_front() {
                 self.image_history.push(image_coordinate);
+                self.fill_upcoming_images();
                 Ok(Some(image_coordinate))
+            } else if self.image_history.is_empty() {
+                // The session can't start
+                Err(anyhow::anyhow!("No image left to draw in the selected sources"))
             } else {
-                Err(anyhow::anyhow!(""))
+                // All the images of the plan were shown, or the sources have no image left
+                // to draw, the session ends instead of failing mid-way
+                Ok(None)
             }
         } else {
-            self.image_history_index = self.image_history_index - 1;
-            Ok(Some(
-                self.image_history[self.image_history.len() - 1 - self.image_history_index],
-            ))
+            self.image_history_index -= 1;
+            Ok(self
+                .displayed_history_position()
+                .map(|position| self.image_history[position]))
         }
     }
 
     fn session_previous_image_coordinates(&mut self) -> Option<ImageCoordinate> {
-        if self.image_history_index < self.image_history.len() - 1 {
-            self.image_history_index = self.image_history_index + 1;
-            Some(self.image_history[self.image_history.len() - 1 - self.image_history_index])
+        if self.image_history_index + 1 < self.image_history.len() {
+            self.image_history_index += 1;
+            self.displayed_history_position().map(|position| self.image_history[position])
         } else {
             None
         }
     }
 
-    fn find_next_image_coordinates(&self) -> Option<ImageCoordinate> {
-        if let Some(config) = self.config.as_ref() {
-            let result = ImageCoordinate::rand(&config.image_sources);
-            for _ in 0..10 {
-                let image_coord = ImageCoordinate::rand(&config.image_sources);
-                if !self.image_history.contains(&image_coord) {
-                    break;
-                }
+    /// Index in the history of the displayed image, `None` before the first image
+    fn displayed_history_position(&self) -> Option<usize> {
+        self.image_history.len().checked_sub(1 + self.image_history_index)
+    }
+
+    /// Pick the next images of the session ahead of time so they can be prefetched
+    fn fill_upcoming_images(&mut self) {
+        let image_count = self
+            .config
+            .as_ref()
+            .map_or(Some(0), |config| config.plan.image_count());
+        while self.upcoming_images.len() < PREFETCH_IMAGE_COUNT
+            && image_count.is_none_or(|image_count| {
+                self.image_history.len() + self.upcoming_images.len() < image_count
+            })
+        {
+            match self.find_next_image_coordinates() {
+                Some(image_coordinate) => self.upcoming_images.push_back(image_coordinate),
+                None => break,
             }
-            Some(result)
-        } else {
-            None
         }
     }
 
+    fn find_next_image_coordinates(&mut self) -> Option<ImageCoordinate> {
+        self.image_selector.as_mut().and_then(ImageSelector::next)
+    }
+
     fn configure_timer(
         &mut self,
         mut on_tick: impl FnMut(Duration) + 'static,
         mut on_timeout: impl FnMut() + 'static,
     ) -> anyhow::Result<()> {
         let config = self.config.as_ref().ok_or(anyhow::anyhow!(""))?;
+        let image_duration = config.plan.image_duration(0).unwrap_or_default();
         {
             let mut timer_data = self.timer_data.borrow_mut();
 
             timer_data.last_tick_date = Instant::now();
-            timer_data.time_left = config.image_duration;
+            timer_data.image_duration = image_duration;
+            timer_data.time_left = image_duration;
         }
 
-        if config.image_duration.is_zero() {
-            on_timeout();
-        } else {
-            let timer_data = self.timer_data.clone();
-            let timer_tick = self.timer_tick.clone();
-            self.timer_tick
-                .start(TimerMode::Repeated, Duration::from_millis(200), move || {
-                    // Update time data
-                    let time_left = {
-                        let mut timer_data_ref = timer_data.borrow_mut();
-
-                        let now = Instant::now();
-                        let delta = now - timer_data_ref.last_tick_date;
-                        timer_data_ref.last_tick_date = now;
-                        if timer_data_ref.time_left <= delta {
-                            let trigger_on_timeout = timer_tick.running();
-                            timer_data_ref.time_left = Duration::default();
-
-                            if trigger_on_timeout {
-                                timer_tick.stop();
-                                on_timeout();
-                            }
-                        } else {
-                            timer_data_ref.time_left -= delta;
+        let timer_data = self.timer_data.clone();
+        let timer_tick = self.timer_tick.clone();
+        self.timer_tick
+            .start(TimerMode::Repeated, Duration::from_millis(200), move || {
+                // Update time data
+                let (time_left, trigger_on_timeout) = {
+                    let mut timer_data_ref = timer_data.borrow_mut();
+
+                    let now = Instant::now();
+                    let delta = now - timer_data_ref.last_tick_date;
+                    timer_data_ref.last_tick_date = now;
+                    let mut trigger_on_timeout = false;
+                    if timer_data_ref.image_duration.is_zero() {
+                        // No timer, the user goes to the next image
+                    } else if timer_data_ref.time_left <= delta {
+                        trigger_on_timeout = timer_tick.running();
+                        timer_data_ref.time_left = Duration::default();
+
+                        if trigger_on_timeout {
+                            timer_tick.stop();
                         }
+                    } else {
+                        timer_data_ref.time_left -= delta;
+                    }
 
-                        timer_data_ref.time_left
-                    };
+                    (timer_data_ref.time_left, trigger_on_timeout)
+                };
 
-                    on_tick(time_left);
-                });
-        }
+                on_tick(time_left);
+                // The timer data is released, the next image or break can update it
+                if trigger_on_timeout {
+                    on_timeout();
+                }
+            });
 
         Ok(())
     }
@@ -247,32 +538,22 @@ impl AppSession {
 struct TimerData {
     last_tick_date: Instant,
     time_left: Duration,
+    /// Duration of the displayed image
+    image_duration: Duration,
 }
 
 impl Default for TimerData {
     fn default() -> Self {
         Self {
             time_left: Duration::default(),
+            image_duration: Duration::default(),
             last_tick_date: Instant::now(),
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct ImageCoordinate {
-    image_source_index: usize,
-    image_index: usize,
-}
-
-impl ImageCoordinate {
-    pub fn rand(image_sources: &[ImageSource]) -> Self {
-        let mut rng = rand::thread_rng();
-        let image_source_index = rng.gen::<usize>() % (image_sources.len());
-        let image_index =
-            rng.gen::<usize>() % (image_sources[image_source_index].check().image_count());
-        Self {
-            image_source_index,
-            image_index,
-        }
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ImageCoordinate {
+    pub(super) image_source_index: usize,
+    pub(super) image_index: usize,
 }
@@ -1,9 +1,100 @@
 use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use slint::SharedString;
 use uuid::Uuid;
 
+use crate::app::image_source::ImageQuality;
+use crate::app::session::plan::SessionPlanPreset;
+use crate::app::session::selection::SelectionStrategy;
+use crate::sg;
+
+/// Settings of the last session, restored in the prepare session page
+///
+/// The seed is not kept, a new session is random unless a seed is typed again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionSettings {
+    image_count: i32,
+    /// seconds
+    image_duration: i32,
+    plan: SessionPlanPreset,
+    /// Segments of the custom plan, kept when another plan is picked
+    custom_plan: String,
+    selection_strategy: SelectionStrategy,
+    /// Images of the last sessions are only shown once all the other images were
+    avoid_recent_images: bool,
+    break_interval: i32,
+    /// Take a break between the segments of the plan
+    break_after_segments: bool,
+    /// seconds
+    break_duration: i32,
+    image_quality: ImageQuality,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            image_count: 5,
+            image_duration: 30,
+            plan: SessionPlanPreset::default(),
+            custom_plan: "10x30s, 5x1m, 2x5m, 1x10m".to_string(),
+            selection_strategy: SelectionStrategy::default(),
+            avoid_recent_images: false,
+            break_interval: 0,
+            break_after_segments: false,
+            break_duration: 120,
+            image_quality: ImageQuality::default(),
+        }
+    }
+}
+
+impl From<&sg::PreparedSessionData> for SessionSettings {
+    fn from(value: &sg::PreparedSessionData) -> Self {
+        Self {
+            image_count: value.used_image_count,
+            image_duration: value.image_duration,
+            plan: value.plan.into(),
+            custom_plan: value.custom_plan.to_string(),
+            selection_strategy: value.selection_strategy.into(),
+            avoid_recent_images: value.avoid_recent_images,
+            break_interval: value.break_interval,
+            break_after_segments: value.break_after_segments,
+            break_duration: value.break_duration,
+            image_quality: value.image_quality.into(),
+        }
+    }
+}
+
+impl From<&SessionSettings> for sg::PreparedSessionData {
+    fn from(value: &SessionSettings) -> Self {
+        Self {
+            available_image_count: 0,
+            duplicate_image_count: 0,
+            image_duration: value.image_duration,
+            used_image_count: value.image_count,
+            selection_strategy: value.selection_strategy.into(),
+            avoid_recent_images: value.avoid_recent_images,
+            seed: SharedString::default(),
+            plan: value.plan.into(),
+            custom_plan: value.custom_plan.as_str().into(),
+            break_interval: value.break_interval,
+            break_after_segments: value.break_after_segments,
+            break_duration: value.break_duration,
+            image_quality: value.image_quality.into(),
+            status: sg::StatusIconData {
+                r#type: sg::StatusIconType::Unknown,
+                error: SharedString::default(),
+            },
+            settings_error: SharedString::default(),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct SessionBackend {
-    used_sources: HashSet<Uuid>
+    used_sources: HashSet<Uuid>,
+    settings: SessionSettings,
 }
 
 impl SessionBackend {
@@ -15,6 +106,14 @@ impl SessionBackend {
         self.used_sources.iter()
     }
 
+    pub fn settings(&self) -> &SessionSettings {
+        &self.settings
+    }
+
+    pub(crate) fn set_settings(&mut self, settings: SessionSettings) {
+        self.settings = settings;
+    }
+
     pub(crate) fn add_image_source(&mut self, uuid: Uuid) -> bool {
         self.used_sources.insert(uuid)
     }
@@ -23,4 +122,3 @@ impl SessionBackend {
         self.used_sources.remove(&uuid)
     }
 }
-
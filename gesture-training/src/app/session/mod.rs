@@ -1,5 +1,10 @@
 pub use app::{AppSession, AppSessionConfiguration};
-pub use backend::SessionBackend;
+pub use backend::{SessionBackend, SessionSettings};
+pub use plan::SessionPlan;
+pub use selection::parse_seed;
 
 mod app;
 mod backend;
+mod plan;
+mod prefetch;
+mod selection;
@@ -0,0 +1,406 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::sg;
+
+/// Largest image count that can be typed in the prepare session page
+pub const MAX_IMAGE_COUNT: usize = 10_000;
+/// Longest image duration that can be typed in the prepare session page
+pub const MAX_IMAGE_DURATION: Duration = Duration::from_secs(3 * 60 * 60);
+
+/// Plans that can be picked in the prepare session page
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SessionPlanPreset {
+    /// Uses the image count and duration of the prepared session
+    #[default]
+    Single,
+    Class,
+    ShortClass,
+    /// Uses the segments typed in the prepare session page, see [`SessionPlan::parse_segments`]
+    Custom,
+}
+
+impl From<sg::SessionPlanPreset> for SessionPlanPreset {
+    fn from(value: sg::SessionPlanPreset) -> Self {
+        match value {
+            sg::SessionPlanPreset::Single => Self::Single,
+            sg::SessionPlanPreset::Class => Self::Class,
+            sg::SessionPlanPreset::ShortClass => Self::ShortClass,
+            sg::SessionPlanPreset::Custom => Self::Custom,
+        }
+    }
+}
+
+impl From<SessionPlanPreset> for sg::SessionPlanPreset {
+    fn from(value: SessionPlanPreset) -> Self {
+        match value {
+            SessionPlanPreset::Single => Self::Single,
+            SessionPlanPreset::Class => Self::Class,
+            SessionPlanPreset::ShortClass => Self::ShortClass,
+            SessionPlanPreset::Custom => Self::Custom,
+        }
+    }
+}
+
+/// Consecutive images of a session displayed for the same duration
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionSegment {
+    /// `None` shows images until the session is stopped
+    image_count: Option<usize>,
+    /// Zero waits for the user to go to the next image
+    image_duration: Duration,
+}
+
+impl SessionSegment {
+    pub fn new(image_count: Option<usize>, image_duration: Duration) -> Self {
+        Self {
+            image_count,
+            image_duration,
+        }
+    }
+}
+
+/// Rest pause taken regularly during a session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionBreaks {
+    /// Number of images between two breaks, `None` when only segments are followed by breaks
+    image_interval: Option<usize>,
+    /// Take a break between two segments, like after the warm-up poses of a class
+    after_segments: bool,
+    duration: Duration,
+}
+
+impl SessionBreaks {
+    pub fn new(image_interval: Option<usize>, after_segments: bool, duration: Duration) -> Self {
+        Self {
+            image_interval,
+            after_segments,
+            duration,
+        }
+    }
+}
+
+/// Ordered segments of a session, like the warm-up poses followed by
+/// longer poses of a figure drawing class
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionPlan {
+    segments: Vec<SessionSegment>,
+    breaks: Option<SessionBreaks>,
+}
+
+impl SessionPlan {
+    pub fn new(segments: Vec<SessionSegment>) -> Self {
+        Self {
+            segments,
+            breaks: None,
+        }
+    }
+
+    pub fn with_breaks(mut self, breaks: Option<SessionBreaks>) -> Self {
+        self.breaks = breaks;
+        self
+    }
+
+    /// All the images are displayed for the same duration
+    pub fn single(image_count: Option<usize>, image_duration: Duration) -> Self {
+        Self::new(vec![SessionSegment::new(image_count, image_duration)])
+    }
+
+    /// 30 minutes class: 10×30s, 5×1min, 2×5min, 1×10min
+    pub fn class() -> Self {
+        Self::new(vec![
+            SessionSegment::new(Some(10), Duration::from_secs(30)),
+            SessionSegment::new(Some(5), Duration::from_secs(60)),
+            SessionSegment::new(Some(2), Duration::from_secs(300)),
+            SessionSegment::new(Some(1), Duration::from_secs(600)),
+        ])
+    }
+
+    /// 10 minutes class: 5×30s, 3×1min, 1×5min
+    pub fn short_class() -> Self {
+        Self::new(vec![
+            SessionSegment::new(Some(5), Duration::from_secs(30)),
+            SessionSegment::new(Some(3), Duration::from_secs(60)),
+            SessionSegment::new(Some(1), Duration::from_secs(300)),
+        ])
+    }
+
+    /// Plan selected in the prepare session page
+    ///
+    /// A used image count of 0 is an unlimited session, an image duration of 0 disables the timer.
+    /// A break duration of 0 disables the breaks, so do a break interval of 0
+    /// without breaks after the segments.
+    pub fn from_prepared_session_data(data: &sg::PreparedSessionData) -> anyhow::Result<Self> {
+        let plan = match data.plan {
+            sg::SessionPlanPreset::Single => {
+                let image_count = Self::parse_image_count("Image count", data.used_image_count)?;
+                let image_duration =
+                    Self::parse_duration("Image duration", data.image_duration)?;
+
+                Self::single((image_count > 0).then_some(image_count), image_duration)
+            }
+            sg::SessionPlanPreset::Class => Self::class(),
+            sg::SessionPlanPreset::ShortClass => Self::short_class(),
+            sg::SessionPlanPreset::Custom => Self::parse_segments(&data.custom_plan)?,
+        };
+
+        let break_interval = Self::parse_image_count("Break interval", data.break_interval)?;
+        let break_duration = Self::parse_duration("Break duration", data.break_duration)?;
+        let image_interval = (break_interval > 0).then_some(break_interval);
+        let breaks = ((image_interval.is_some() || data.break_after_segments)
+            && !break_duration.is_zero())
+        .then(|| SessionBreaks::new(image_interval, data.break_after_segments, break_duration));
+
+        Ok(plan.with_breaks(breaks))
+    }
+
+    /// Segments typed like `10x30s, 5x1m, 2x5m`, in order
+    ///
+    /// Each segment is an image count and a duration in seconds, or with a `s`, `m` or `h` unit.
+    /// The count of the last segment can be `*` to show images until the session is stopped,
+    /// a duration of 0 waits for the user to go to the next image.
+    pub fn parse_segments(text: &str) -> anyhow::Result<Self> {
+        let segments = text
+            .split(',')
+            .map(str::trim)
+            .filter(|segment| !segment.is_empty())
+            .map(Self::parse_segment)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if segments.is_empty() {
+            anyhow::bail!("The custom plan has no segment, type them like 10x30s, 5x1m");
+        }
+        if segments[..segments.len() - 1]
+            .iter()
+            .any(|segment| segment.image_count.is_none())
+        {
+            anyhow::bail!("Only the last segment of the custom plan can have unlimited images");
+        }
+
+        Ok(Self::new(segments))
+    }
+
+    fn parse_segment(text: &str) -> anyhow::Result<SessionSegment> {
+        let invalid = || anyhow::anyhow!("Segment \"{text}\" is not like 10x30s");
+        let (image_count, duration) = text
+            .split_once(['x', 'X', '×'])
+            .ok_or_else(invalid)?;
+
+        let image_count = match image_count.trim() {
+            "*" => None,
+            image_count => {
+                let image_count = image_count.parse::<usize>().map_err(|_| invalid())?;
+                if !(1..=MAX_IMAGE_COUNT).contains(&image_count) {
+                    anyhow::bail!(
+                        "Segment \"{text}\" image count is not between 1 and {MAX_IMAGE_COUNT}"
+                    );
+                }
+                Some(image_count)
+            }
+        };
+
+        let duration = duration.trim();
+        let (value, unit_seconds) = match duration.char_indices().last() {
+            Some((index, 's')) => (&duration[..index], 1),
+            Some((index, 'm')) => (&duration[..index], 60),
+            Some((index, 'h')) => (&duration[..index], 60 * 60),
+            _ => (duration, 1),
+        };
+        let image_duration = value
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|value| value.checked_mul(unit_seconds))
+            .map(Duration::from_secs)
+            .ok_or_else(invalid)?;
+        if image_duration > MAX_IMAGE_DURATION {
+            anyhow::bail!(
+                "Segment \"{text}\" duration is not between 0s and {}s",
+                MAX_IMAGE_DURATION.as_secs()
+            );
+        }
+
+        Ok(SessionSegment::new(image_count, image_duration))
+    }
+
+    fn parse_image_count(name: &str, value: i32) -> anyhow::Result<usize> {
+        usize::try_from(value)
+            .ok()
+            .filter(|image_count| *image_count <= MAX_IMAGE_COUNT)
+            .ok_or_else(|| anyhow::anyhow!("{name} {value} is not between 0 and {MAX_IMAGE_COUNT}"))
+    }
+
+    /// `value` is in seconds
+    fn parse_duration(name: &str, value: i32) -> anyhow::Result<Duration> {
+        u64::try_from(value)
+            .ok()
+            .map(Duration::from_secs)
+            .filter(|duration| *duration <= MAX_IMAGE_DURATION)
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{name} {value}s is not between 0s and {}s",
+                    MAX_IMAGE_DURATION.as_secs()
+                )
+            })
+    }
+
+    /// `None` when the session is unlimited
+    pub fn image_count(&self) -> Option<usize> {
+        self.segments
+            .iter()
+            .map(|segment| segment.image_count)
+            .sum()
+    }
+
+    /// Duration of the break taken after the image at `position`, if any
+    ///
+    /// There is no break after the last image
+    pub fn break_after(&self, position: usize) -> Option<Duration> {
+        let breaks = self.breaks?;
+        let shown_image_count = position + 1;
+        if self.image_count() == Some(shown_image_count) {
+            return None;
+        }
+
+        let is_interval_end = breaks
+            .image_interval
+            .and_then(|image_interval| shown_image_count.checked_rem(image_interval))
+            == Some(0);
+        let is_segment_end = breaks.after_segments && self.is_segment_end(position);
+        (is_interval_end || is_segment_end).then_some(breaks.duration)
+    }
+
+    /// Whether the image at `position` is the last one of its segment
+    fn is_segment_end(&self, position: usize) -> bool {
+        let mut shown_image_count = 0;
+        for segment in &self.segments {
+            let Some(image_count) = segment.image_count else {
+                return false;
+            };
+            shown_image_count += image_count;
+            if shown_image_count == position + 1 {
+                return true;
+            }
+            if shown_image_count > position + 1 {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Duration of the image at `position` in the session, `None` past the end of the plan
+    pub fn image_duration(&self, position: usize) -> Option<Duration> {
+        let mut first_position = 0;
+        for segment in &self.segments {
+            match segment.image_count {
+                Some(image_count) if position >= first_position + image_count => {
+                    first_position += image_count;
+                }
+                _ => return Some(segment.image_duration),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_segments_in_order() {
+        let plan = SessionPlan::parse_segments("10x30s, 5x1m,2X90 ,1×1h").unwrap();
+        assert_eq!(
+            plan,
+            SessionPlan::new(vec![
+                SessionSegment::new(Some(10), Duration::from_secs(30)),
+                SessionSegment::new(Some(5), Duration::from_secs(60)),
+                SessionSegment::new(Some(2), Duration::from_secs(90)),
+                SessionSegment::new(Some(1), Duration::from_secs(3600)),
+            ])
+        );
+        assert_eq!(plan.image_count(), Some(18));
+    }
+
+    #[test]
+    fn parse_segments_unlimited_last() {
+        let plan = SessionPlan::parse_segments("5x30s, *x0").unwrap();
+        assert_eq!(plan.image_count(), None);
+        assert_eq!(plan.image_duration(100), Some(Duration::ZERO));
+
+        assert!(SessionPlan::parse_segments("*x30s, 5x1m").is_err());
+    }
+
+    #[test]
+    fn breaks_at_an_interval() {
+        let breaks = SessionBreaks::new(Some(5), false, Duration::from_secs(60));
+        let plan = SessionPlan::single(Some(12), Duration::from_secs(30)).with_breaks(Some(breaks));
+        let positions = (0..12)
+            .filter(|position| plan.break_after(*position).is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [4, 9]);
+        assert_eq!(plan.break_after(4), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn no_break_after_the_last_image() {
+        let breaks = SessionBreaks::new(Some(5), true, Duration::from_secs(60));
+        let plan = SessionPlan::single(Some(10), Duration::from_secs(30)).with_breaks(Some(breaks));
+        assert!(plan.break_after(4).is_some());
+        assert_eq!(plan.break_after(9), None);
+
+        let plan = SessionPlan::class().with_breaks(Some(breaks));
+        assert_eq!(plan.break_after(17), None);
+    }
+
+    #[test]
+    fn breaks_of_unlimited_plans() {
+        let breaks = SessionBreaks::new(Some(5), true, Duration::from_secs(60));
+        let plan = SessionPlan::single(None, Duration::from_secs(30)).with_breaks(Some(breaks));
+        assert!(plan.break_after(4).is_some());
+        assert!(plan.break_after(9_999).is_some());
+        assert_eq!(plan.break_after(10_000), None);
+
+        // The last segment never ends
+        let breaks = SessionBreaks::new(None, true, Duration::from_secs(60));
+        let plan = SessionPlan::parse_segments("2x30s, *x1m")
+            .unwrap()
+            .with_breaks(Some(breaks));
+        let positions = (0..100)
+            .filter(|position| plan.break_after(*position).is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [1]);
+    }
+
+    #[test]
+    fn breaks_after_segments() {
+        let breaks = SessionBreaks::new(None, true, Duration::from_secs(120));
+        let plan = SessionPlan::class().with_breaks(Some(breaks));
+        let positions = (0..18)
+            .filter(|position| plan.break_after(*position).is_some())
+            .collect::<Vec<_>>();
+        // After the 10×30s, the 5×1min and the 2×5min segments
+        assert_eq!(positions, [9, 14, 16]);
+
+        let breaks = SessionBreaks::new(Some(4), true, Duration::from_secs(120));
+        let plan = SessionPlan::class().with_breaks(Some(breaks));
+        let positions = (0..18)
+            .filter(|position| plan.break_after(*position).is_some())
+            .collect::<Vec<_>>();
+        assert_eq!(positions, [3, 7, 9, 11, 14, 15, 16]);
+    }
+
+    #[test]
+    fn no_breaks() {
+        let plan = SessionPlan::class();
+        assert!((0..18).all(|position| plan.break_after(position).is_none()));
+    }
+
+    #[test]
+    fn parse_segments_invalid() {
+        for text in ["", " , ", "10", "0x30s", "10x", "10x30d", "10x4h", "ax30s", "10x-1"] {
+            assert!(SessionPlan::parse_segments(text).is_err(), "{text}");
+        }
+    }
+}
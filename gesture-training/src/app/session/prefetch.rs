@@ -0,0 +1,280 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+
+use crate::app::image_source::{DisplayTarget, ImageSource, ImageSourceTrait};
+use crate::app::log::Log;
+use crate::app::session::app::ImageCoordinate;
+
+/// Number of images decoded ahead of the displayed one
+pub const PREFETCH_IMAGE_COUNT: usize = 3;
+/// Maximum memory used by decoded images that are not displayed yet
+///
+/// Decoded sizes are only known once loaded, images are prefetched one at a time
+/// so the budget is exceeded by one image at most
+pub const PREFETCH_MEMORY_BUDGET: usize = 256 * 1024 * 1024;
+/// Maximum memory used by the images already displayed during the session,
+/// kept to show them again without decoding them
+pub const SESSION_CACHE_MEMORY_BUDGET: usize = 256 * 1024 * 1024;
+
+enum PrefetchedImage {
+    Loading,
+    Loaded(slint::Image),
+}
+
+impl PrefetchedImage {
+    fn memory_size(&self) -> usize {
+        match self {
+            PrefetchedImage::Loading => 0,
+            PrefetchedImage::Loaded(image) => {
+                let size = image.size();
+                size.width as usize * size.height as usize * 4
+            }
+        }
+    }
+}
+
+/// Decode session images in the background before they are displayed
+///
+/// Images are decoded at the size of the window, the displayed ones
+/// are cached for the rest of the session.
+pub struct ImagePrefetcher {
+    images: HashMap<ImageCoordinate, PrefetchedImage>,
+    /// Images already displayed, most recent last
+    displayed: VecDeque<ImageCoordinate>,
+    /// Image to display as soon as it is loaded
+    awaited: Option<ImageCoordinate>,
+    /// Images to decode ahead of time, in display order
+    queued: VecDeque<(ImageCoordinate, ImageSource)>,
+    on_image_loaded: Option<Rc<dyn Fn(slint::Image)>>,
+    /// Called when the awaited image can't be loaded
+    on_image_failed: Option<Rc<dyn Fn()>>,
+    display_target: DisplayTarget,
+}
+
+impl ImagePrefetcher {
+    pub fn new(
+        on_image_loaded: Option<Rc<dyn Fn(slint::Image)>>,
+        on_image_failed: Option<Rc<dyn Fn()>>,
+        display_target: DisplayTarget,
+    ) -> Self {
+        Self {
+            images: HashMap::new(),
+            displayed: VecDeque::new(),
+            awaited: None,
+            queued: VecDeque::new(),
+            on_image_loaded,
+            on_image_failed,
+            display_target,
+        }
+    }
+
+    /// Memory used by the images that were not displayed yet
+    fn prefetched_memory_size(&self) -> usize {
+        self.images
+            .iter()
+            .filter(|(image_coordinate, _)| !self.displayed.contains(image_coordinate))
+            .map(|(_, image)| image.memory_size())
+            .sum()
+    }
+
+    /// Release the images displayed the longest time ago until the cache fits in its budget
+    fn evict_displayed_images(&mut self) {
+        let mut cached_memory_size = self
+            .displayed
+            .iter()
+            .filter_map(|image_coordinate| self.images.get(image_coordinate))
+            .map(PrefetchedImage::memory_size)
+            .sum::<usize>();
+
+        while cached_memory_size > SESSION_CACHE_MEMORY_BUDGET {
+            let Some(image_coordinate) = self.displayed.pop_front() else {
+                break;
+            };
+            if let Some(image) = self.images.remove(&image_coordinate) {
+                cached_memory_size -= image.memory_size();
+            }
+        }
+    }
+
+    /// Stop displaying images, pending loads will be dropped
+    pub fn clear(&mut self) {
+        self.images.clear();
+        self.displayed.clear();
+        self.awaited = None;
+        self.queued.clear();
+        self.on_image_loaded = None;
+        self.on_image_failed = None;
+    }
+
+    /// Display an image as soon as it is available
+    ///
+    /// Returns `true` when the image was already decoded
+    pub fn show(
+        this: &Rc<RefCell<Self>>,
+        image_coordinate: ImageCoordinate,
+        image_source: &ImageSource,
+    ) -> anyhow::Result<bool> {
+        let is_loaded = {
+            let mut prefetcher = this.borrow_mut();
+            prefetcher.awaited = Some(image_coordinate);
+            matches!(
+                prefetcher.images.get(&image_coordinate),
+                Some(PrefetchedImage::Loaded(_))
+            )
+        };
+
+        if is_loaded {
+            // Display it on the next iteration of the event loop, callers are
+            // usually still borrowing the session.
+            let this = this.clone();
+            slint::spawn_local(async move {
+                Self::show_if_awaited(&this, image_coordinate);
+            })?;
+        } else {
+            Self::load(this, image_coordinate, image_source)?;
+        }
+
+        Ok(is_loaded)
+    }
+
+    /// Decode images ahead of time, one at a time and within the memory budget
+    ///
+    /// Images that are neither awaited, displayed before nor in `image_coordinates` are released
+    pub fn prefetch(
+        this: &Rc<RefCell<Self>>,
+        image_coordinates: impl IntoIterator<Item = (ImageCoordinate, ImageSource)>,
+    ) -> anyhow::Result<()> {
+        {
+            let prefetcher = &mut *this.borrow_mut();
+            prefetcher.queued = image_coordinates.into_iter().collect();
+            let awaited = prefetcher.awaited;
+            let displayed = &prefetcher.displayed;
+            let queued = &prefetcher.queued;
+            prefetcher.images.retain(|image_coordinate, _| {
+                Some(*image_coordinate) == awaited
+                    || displayed.contains(image_coordinate)
+                    || queued
+                        .iter()
+                        .any(|(coordinate, _)| coordinate == image_coordinate)
+            });
+        }
+
+        Self::load_next_queued(this)
+    }
+
+    /// Start decoding the next queued image, unless an image is already prefetching
+    /// or the memory budget is reached
+    fn load_next_queued(this: &Rc<RefCell<Self>>) -> anyhow::Result<()> {
+        let next = {
+            let mut prefetcher = this.borrow_mut();
+            let awaited = prefetcher.awaited;
+            let is_prefetching = prefetcher.images.iter().any(|(image_coordinate, image)| {
+                matches!(image, PrefetchedImage::Loading) && Some(*image_coordinate) != awaited
+            });
+            if is_prefetching || prefetcher.prefetched_memory_size() >= PREFETCH_MEMORY_BUDGET {
+                None
+            } else {
+                // Images already loaded or loading are skipped
+                loop {
+                    match prefetcher.queued.pop_front() {
+                        Some((image_coordinate, _))
+                            if prefetcher.images.contains_key(&image_coordinate) => {}
+                        next => break next,
+                    }
+                }
+            }
+        };
+
+        match next {
+            Some((image_coordinate, image_source)) => {
+                Self::load(this, image_coordinate, &image_source)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn load(
+        this: &Rc<RefCell<Self>>,
+        image_coordinate: ImageCoordinate,
+        image_source: &ImageSource,
+    ) -> anyhow::Result<()> {
+        {
+            let mut prefetcher = this.borrow_mut();
+            if prefetcher.images.contains_key(&image_coordinate) {
+                return Ok(());
+            }
+            prefetcher
+                .images
+                .insert(image_coordinate, PrefetchedImage::Loading);
+        }
+
+        let this = this.clone();
+        let image_source = image_source.clone();
+        let display_target = this.borrow().display_target;
+        slint::spawn_local(async move {
+            match image_source
+                .load_image(image_coordinate.image_index, display_target)
+                .await
+            {
+                Ok(image) => {
+                    {
+                        let mut prefetcher = this.borrow_mut();
+                        // The image was released while loading
+                        if let Some(entry) = prefetcher.images.get_mut(&image_coordinate) {
+                            *entry = PrefetchedImage::Loaded(image);
+                        }
+                    }
+                    Self::show_if_awaited(&this, image_coordinate);
+                }
+                Err(error) => {
+                    Log::handle_error(&error);
+                    let on_image_failed = {
+                        let mut prefetcher = this.borrow_mut();
+                        prefetcher.images.remove(&image_coordinate);
+                        if prefetcher.awaited == Some(image_coordinate) {
+                            prefetcher.awaited = None;
+                            prefetcher.on_image_failed.clone()
+                        } else {
+                            None
+                        }
+                    };
+                    if let Some(callback) = on_image_failed {
+                        callback();
+                    }
+                }
+            }
+            if let Err(error) = Self::load_next_queued(&this) {
+                Log::handle_error(&error);
+            }
+        })?;
+
+        Ok(())
+    }
+
+    fn show_if_awaited(this: &Rc<RefCell<Self>>, image_coordinate: ImageCoordinate) {
+        let to_show = {
+            let mut prefetcher = this.borrow_mut();
+            let image = match prefetcher.images.get(&image_coordinate) {
+                Some(PrefetchedImage::Loaded(image)) if prefetcher.awaited == Some(image_coordinate) => {
+                    Some(image.clone())
+                }
+                _ => None,
+            };
+            image.and_then(|image| {
+                prefetcher.awaited = None;
+                prefetcher.displayed.retain(|displayed| *displayed != image_coordinate);
+                prefetcher.displayed.push_back(image_coordinate);
+                prefetcher.evict_displayed_images();
+                prefetcher
+                    .on_image_loaded
+                    .clone()
+                    .map(|callback| (callback, image))
+            })
+        };
+
+        if let Some((callback, image)) = to_show {
+            callback(image);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::HashSet;
+
+use rand::distributions::WeightedIndex;
+use rand::prelude::Distribution;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::app::session::app::ImageCoordinate;
+use crate::app::stable_hash::stable_hash;
+use crate::sg;
+
+/// How images are spread between the sources of a session
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelectionStrategy {
+    /// Every image has the same chance to be picked, large sources are shown more often
+    #[default]
+    UniformImages,
+    /// Every source has the same chance to be picked, whatever its size
+    UniformSources,
+    /// Sources are picked according to their weight
+    Weighted,
+}
+
+/// Turn the seed typed by the user into an RNG seed
+///
+/// Numbers are used as is, any other text is hashed so a word can be shared as a session code.
+/// Returns `None` when no seed was typed.
+pub fn parse_seed(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else if let Ok(seed) = text.parse::<u64>() {
+        Some(seed)
+    } else {
+        Some(stable_hash(text.as_bytes()))
+    }
+}
+
+impl SelectionStrategy {
+    /// Chance to pick a source that still has images to draw,
+    /// the uniform images strategy uses the size of the bags instead
+    fn weight(self, source: &SourceBag) -> f64 {
+        match self {
+            SelectionStrategy::UniformImages | SelectionStrategy::UniformSources => 1.0,
+            SelectionStrategy::Weighted => source.weight.max(0.0),
+        }
+    }
+}
+
+impl From<sg::SelectionStrategy> for SelectionStrategy {
+    fn from(value: sg::SelectionStrategy) -> Self {
+        match value {
+            sg::SelectionStrategy::UniformImages => Self::UniformImages,
+            sg::SelectionStrategy::UniformSources => Self::UniformSources,
+            sg::SelectionStrategy::Weighted => Self::Weighted,
+        }
+    }
+}
+
+impl From<SelectionStrategy> for sg::SelectionStrategy {
+    fn from(value: SelectionStrategy) -> Self {
+        match value {
+            SelectionStrategy::UniformImages => Self::UniformImages,
+            SelectionStrategy::UniformSources => Self::UniformSources,
+            SelectionStrategy::Weighted => Self::Weighted,
+        }
+    }
+}
+
+/// Shuffle-bag of the images of one source
+///
+/// Every image is drawn once before any image is drawn again.
+/// Images shown during recent sessions are only drawn once all
+/// the other images were.
+/// Excluded images, like the duplicates of images of other sources, are never drawn.
+struct SourceBag {
+    image_count: usize,
+    weight: f64,
+    excluded_images: HashSet<usize>,
+    recent_images: HashSet<usize>,
+    /// Images left to draw, drawn from the end
+    bag: Vec<usize>,
+    /// Number of recent images left at the start of the bag
+    recent_left: usize,
+}
+
+impl SourceBag {
+    fn refill(&mut self, rng: &mut impl Rng) {
+        let (mut recent, mut fresh): (Vec<_>, Vec<_>) = (0..self.image_count)
+            .filter(|image_index| !self.excluded_images.contains(image_index))
+            .partition(|image_index| self.recent_images.contains(image_index));
+        recent.shuffle(rng);
+        fresh.shuffle(rng);
+
+        // Recent images are at the start so they are drawn last
+        self.recent_left = recent.len();
+        self.bag = recent;
+        self.bag.append(&mut fresh);
+
+        // Once the bag was emptied, all images were seen again
+        self.recent_images.clear();
+    }
+
+    fn fresh_len(&self) -> usize {
+        self.bag.len() - self.recent_left
+    }
+
+    fn draw(&mut self, rng: &mut impl Rng) -> Option<usize> {
+        if self.bag.is_empty() {
+            self.refill(rng);
+        }
+        if self.fresh_len() == 0 {
+            self.recent_left = self.recent_left.saturating_sub(1);
+        }
+        self.bag.pop()
+    }
+}
+
+/// Draw the images of a session from the shuffle-bags of its sources
+///
+/// The images drawn only depend on the seed, the sources and the recent images
+pub struct ImageSelector {
+    strategy: SelectionStrategy,
+    sources: Vec<SourceBag>,
+    rng: ChaCha8Rng,
+}
+
+impl ImageSelector {
+    /// `sources` contains the image count and the weight of each source of the session
+    pub fn new(
+        strategy: SelectionStrategy,
+        sources: impl IntoIterator<Item = (usize, f64)>,
+        recent_images: &HashSet<ImageCoordinate>,
+        excluded_images: &HashSet<ImageCoordinate>,
+        seed: u64,
+    ) -> Self {
+        let sources = sources
+            .into_iter()
+            .enumerate()
+            .map(|(image_source_index, (image_count, weight))| SourceBag {
+                image_count,
+                weight,
+                // Coordinates from an older check may be past the end of the source
+                excluded_images: excluded_images
+                    .iter()
+                    .filter(|image| image.image_source_index == image_source_index)
+                    .map(|image| image.image_index)
+                    .filter(|image_index| *image_index < image_count)
+                    .collect(),
+                recent_images: recent_images
+                    .iter()
+                    .filter(|image| image.image_source_index == image_source_index)
+                    .map(|image| image.image_index)
+                    .collect(),
+                bag: Vec::new(),
+                recent_left: 0,
+            })
+            .collect();
+
+        Self {
+            strategy,
+            sources,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+        }
+    }
+
+    fn pick_source(&mut self) -> Option<usize> {
+        let weights = match self.strategy {
+            SelectionStrategy::UniformImages => {
+                // Behave like a single bag containing the images of all the sources
+                if self.sources.iter().all(|source| source.bag.is_empty()) {
+                    for source in self.sources.iter_mut() {
+                        source.refill(&mut self.rng);
+                    }
+                }
+                if self.sources.iter().any(|source| source.fresh_len() > 0) {
+                    self.sources
+                        .iter()
+                        .map(|source| source.fresh_len() as f64)
+                        .collect::<Vec<_>>()
+                } else {
+                    self.sources
+                        .iter()
+                        .map(|source| source.bag.len() as f64)
+                        .collect::<Vec<_>>()
+                }
+            }
+            SelectionStrategy::UniformSources | SelectionStrategy::Weighted => {
+                // Emptied sources wait for the others, so no image is repeated
+                // before all the images of all the sources were drawn
+                if self
+                    .sources
+                    .iter()
+                    .all(|source| source.bag.is_empty() || self.strategy.weight(source) == 0.0)
+                {
+                    for source in self.sources.iter_mut() {
+                        source.refill(&mut self.rng);
+                    }
+                }
+                self.sources
+                    .iter()
+                    .map(|source| {
+                        if source.bag.is_empty() {
+                            0.0
+                        } else {
+                            self.strategy.weight(source)
+                        }
+                    })
+                    .collect()
+            }
+        };
+
+        // All weights are zero: nothing can be picked
+        let distribution = WeightedIndex::new(weights).ok()?;
+        Some(distribution.sample(&mut self.rng))
+    }
+}
+
+impl Iterator for ImageSelector {
+    type Item = ImageCoordinate;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let image_source_index = self.pick_source()?;
+        let image_index = self.sources[image_source_index].draw(&mut self.rng)?;
+        Some(ImageCoordinate {
+            image_source_index,
+            image_index,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn coordinates(image_source_index: usize, image_indices: &[usize]) -> HashSet<ImageCoordinate> {
+        image_indices
+            .iter()
+            .map(|image_index| ImageCoordinate {
+                image_source_index,
+                image_index: *image_index,
+            })
+            .collect()
+    }
+
+    fn selector(
+        strategy: SelectionStrategy,
+        sources: &[(usize, f64)],
+        recent_images: &HashSet<ImageCoordinate>,
+        excluded_images: &HashSet<ImageCoordinate>,
+        seed: u64,
+    ) -> ImageSelector {
+        ImageSelector::new(
+            strategy,
+            sources.iter().copied(),
+            recent_images,
+            excluded_images,
+            seed,
+        )
+    }
+
+    #[test]
+    fn no_repeat_until_the_bag_is_empty() {
+        let none = HashSet::new();
+        let mut images = selector(
+            SelectionStrategy::UniformImages,
+            &[(3, 1.0), (4, 1.0)],
+            &none,
+            &none,
+            1,
+        );
+
+        for _ in 0..3 {
+            let drawn = images.by_ref().take(7).collect::<HashSet<_>>();
+            assert_eq!(drawn.len(), 7);
+        }
+    }
+
+    #[test]
+    fn no_repeat_within_a_source() {
+        let none = HashSet::new();
+        for strategy in [
+            SelectionStrategy::UniformSources,
+            SelectionStrategy::Weighted,
+        ] {
+            let images = selector(strategy, &[(5, 1.0)], &none, &none, 2);
+            let drawn = images.take(5).collect::<HashSet<_>>();
+            assert_eq!(drawn, coordinates(0, &[0, 1, 2, 3, 4]));
+        }
+    }
+
+    #[test]
+    fn no_repeat_with_unequal_sources() {
+        let none = HashSet::new();
+        for strategy in [
+            SelectionStrategy::UniformSources,
+            SelectionStrategy::Weighted,
+        ] {
+            let mut images = selector(strategy, &[(3, 1.0), (500, 1.0)], &none, &none, 8);
+            let drawn = images.by_ref().take(503).collect::<HashSet<_>>();
+            assert_eq!(drawn.len(), 503);
+
+            // Once every image was drawn, the small source is used again
+            let drawn = images.take(100).collect::<Vec<_>>();
+            assert!(drawn.iter().any(|image| image.image_source_index == 0));
+        }
+    }
+
+    #[test]
+    fn excluded_images_past_the_end_are_ignored() {
+        let none = HashSet::new();
+        let excluded_images = coordinates(0, &[1, 5, 6, 7]);
+        let images = selector(
+            SelectionStrategy::UniformSources,
+            &[(3, 1.0)],
+            &none,
+            &excluded_images,
+            9,
+        );
+        assert_eq!(
+            images.take(2).collect::<HashSet<_>>(),
+            coordinates(0, &[0, 2])
+        );
+    }
+
+    #[test]
+    fn recent_images_are_drawn_last() {
+        let none = HashSet::new();
+        let recent_images = coordinates(0, &[0, 1, 2]);
+        let images = selector(
+            SelectionStrategy::UniformImages,
+            &[(10, 1.0)],
+            &recent_images,
+            &none,
+            3,
+        )
+        .take(10)
+        .collect::<Vec<_>>();
+
+        assert!(images[..7]
+            .iter()
+            .all(|image| !recent_images.contains(image)));
+        assert_eq!(
+            images[7..].iter().copied().collect::<HashSet<_>>(),
+            recent_images
+        );
+    }
+
+    #[test]
+    fn excluded_images_are_never_drawn() {
+        let none = HashSet::new();
+        let excluded_images = coordinates(1, &[1, 3]);
+        for strategy in [
+            SelectionStrategy::UniformImages,
+            SelectionStrategy::UniformSources,
+            SelectionStrategy::Weighted,
+        ] {
+            let images = selector(strategy, &[(2, 1.0), (4, 1.0)], &none, &excluded_images, 4);
+            assert!(images
+                .take(50)
+                .all(|image| !excluded_images.contains(&image)));
+        }
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_images() {
+        let none = HashSet::new();
+        let sources = [(50, 1.0), (30, 2.0)];
+        for strategy in [
+            SelectionStrategy::UniformImages,
+            SelectionStrategy::UniformSources,
+            SelectionStrategy::Weighted,
+        ] {
+            let first = selector(strategy, &sources, &none, &none, 5)
+                .take(40)
+                .collect::<Vec<_>>();
+            let second = selector(strategy, &sources, &none, &none, 5)
+                .take(40)
+                .collect::<Vec<_>>();
+            let other = selector(strategy, &sources, &none, &none, 6)
+                .take(40)
+                .collect::<Vec<_>>();
+            assert_eq!(first, second);
+            assert_ne!(first, other);
+        }
+    }
+
+    #[test]
+    fn nothing_to_draw() {
+        let none = HashSet::new();
+        let mut images = selector(
+            SelectionStrategy::Weighted,
+            &[(5, 0.0), (3, 0.0)],
+            &none,
+            &none,
+            7,
+        );
+        assert_eq!(images.next(), None);
+
+        let mut images = selector(
+            SelectionStrategy::UniformImages,
+            &[(0, 1.0)],
+            &none,
+            &none,
+            7,
+        );
+        assert_eq!(images.next(), None);
+
+        let excluded_images = coordinates(0, &[0, 1]);
+        let mut images = selector(
+            SelectionStrategy::UniformSources,
+            &[(2, 1.0)],
+            &none,
+            &excluded_images,
+            7,
+        );
+        assert_eq!(images.next(), None);
+    }
+}
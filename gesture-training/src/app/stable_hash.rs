@@ -0,0 +1,38 @@
+use std::hash::Hasher;
+
+/// FNV-1a hasher, stable between builds unlike the std hasher
+///
+/// Used for the names of cached files and for the seeds typed by the user,
+/// which must hash the same on every run and every platform.
+/// Bytes are hashed as written, use [`Hasher::write`] rather than [`std::hash::Hash`]
+/// to keep the hashes of the existing cached files.
+pub struct StableHasher {
+    hash: u64,
+}
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self {
+            hash: 0xcbf29ce484222325,
+        }
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.hash = (self.hash ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Hash of a single slice of bytes, see [`StableHasher`]
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hasher = StableHasher::default();
+    hasher.write(bytes);
+    hasher.finish()
+}